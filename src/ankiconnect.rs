@@ -0,0 +1,415 @@
+use crate::GeneratedClip;
+use crate::note::NoteFields;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+
+/// The address AnkiConnect listens on by default.
+pub const DEFAULT_URL: &str = "http://127.0.0.1:8765";
+
+/// The AnkiConnect API version this client speaks.
+const API_VERSION: u32 = 6;
+
+/// AnkiConnect sends requests to the AnkiConnect add-on of a running Anki.
+pub struct AnkiConnect {
+    url: String,
+    client: reqwest::Client,
+}
+
+/// NoteOptions controls where and how notes are added to Anki.
+#[derive(Debug, Clone)]
+pub struct NoteOptions {
+    pub deck: String,
+    pub note_type: String,
+    pub tags: Vec<String>,
+    pub fields: FieldMapping,
+}
+
+/// FieldMapping names the note type field that each generated value goes into.
+///
+/// Several values may share a field, in which case the non-empty ones are
+/// joined with a space.
+#[derive(Debug, Clone)]
+pub struct FieldMapping {
+    pub sentence: String,
+    pub meaning: String,
+    pub audio: String,
+    pub image: String,
+    /// Video clips are only added to notes if this field is set.
+    pub video: Option<String>,
+}
+
+/// NoteOutcome is the result of adding a single clip's note to Anki.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteOutcome {
+    Added(i64),
+    Duplicate,
+    Failed(String),
+}
+
+impl AnkiConnect {
+    pub fn new(url: impl Into<String>) -> Self {
+        AnkiConnect {
+            url: url.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Uploads `clips`' media and adds one note per clip.
+    ///
+    /// Returns the outcome for each clip, in the same order as `clips`. Notes
+    /// that Anki reports as duplicates or otherwise rejects are skipped rather
+    /// than failing the whole batch.
+    pub(crate) async fn push_clips(
+        &self,
+        clips: &[GeneratedClip],
+        options: &NoteOptions,
+    ) -> Result<Vec<NoteOutcome>, Box<dyn std::error::Error>> {
+        for path in clips.iter().flat_map(GeneratedClip::media_paths) {
+            self.store_media_file(path).await?;
+        }
+
+        let notes = clips
+            .iter()
+            .map(|generated| Ok(note_json(&NoteFields::from_clip(generated)?, options)))
+            .collect::<Result<Vec<Value>, Box<dyn std::error::Error>>>()?;
+
+        // `addNotes` fails the whole request if any note is rejected, so check
+        // up front which notes can be added and only send those.
+        let checks = self
+            .invoke("canAddNotesWithErrorDetail", json!({ "notes": notes }))
+            .await?;
+        let checks = checks
+            .as_array()
+            .filter(|checks| checks.len() == notes.len())
+            .ok_or("unexpected canAddNotesWithErrorDetail result")?;
+
+        let mut outcomes = Vec::with_capacity(notes.len());
+        let mut addable = Vec::new();
+        for (note, check) in notes.into_iter().zip(checks) {
+            if check["canAdd"].as_bool() == Some(true) {
+                outcomes.push(None);
+                addable.push(note);
+            } else {
+                let error = check["error"].as_str().unwrap_or("unknown error");
+                outcomes.push(Some(if error.contains("duplicate") {
+                    NoteOutcome::Duplicate
+                } else {
+                    NoteOutcome::Failed(error.to_string())
+                }));
+            }
+        }
+
+        let mut ids = Vec::new().into_iter();
+        if !addable.is_empty() {
+            let added = self.invoke("addNotes", json!({ "notes": addable })).await?;
+            ids = added
+                .as_array()
+                .ok_or("unexpected addNotes result")?
+                .iter()
+                .map(|id| id.as_i64())
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+
+        Ok(outcomes
+            .into_iter()
+            .map(|outcome| {
+                outcome.unwrap_or_else(|| match ids.next().flatten() {
+                    Some(id) => NoteOutcome::Added(id),
+                    None => NoteOutcome::Failed("note was not added".to_string()),
+                })
+            })
+            .collect())
+    }
+
+    // Copies the file at `path` into Anki's media folder under its file name.
+    async fn store_media_file(
+        &self,
+        path: &std::path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let filename = crate::note::file_name(path)?;
+        let path = path.to_str().ok_or("media path is not valid UTF-8")?;
+        self.invoke(
+            "storeMediaFile",
+            json!({ "filename": filename, "path": path }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    // Sends a single AnkiConnect action and returns its `result`.
+    async fn invoke(
+        &self,
+        action: &str,
+        params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let request = json!({
+            "action": action,
+            "version": API_VERSION,
+            "params": params,
+        });
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        match &response["error"] {
+            Value::Null => Ok(response["result"].clone()),
+            error => Err(format!(
+                "AnkiConnect {} failed: {}",
+                action,
+                error.as_str().unwrap_or(&error.to_string())
+            )
+            .into()),
+        }
+    }
+}
+
+impl Default for AnkiConnect {
+    fn default() -> Self {
+        AnkiConnect::new(DEFAULT_URL)
+    }
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        FieldMapping {
+            sentence: "Sentence".to_string(),
+            meaning: "Meaning".to_string(),
+            audio: "Audio".to_string(),
+            image: "Image".to_string(),
+            video: None,
+        }
+    }
+}
+
+fn note_json(fields: &NoteFields, options: &NoteOptions) -> Value {
+    let mut mapped: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, value) in [
+        (&options.fields.sentence, &fields.sentence),
+        (&options.fields.meaning, &fields.meaning),
+        (&options.fields.audio, &fields.audio),
+        (&options.fields.image, &fields.image),
+    ]
+    .into_iter()
+    .chain(
+        options
+            .fields
+            .video
+            .as_ref()
+            .map(|name| (name, &fields.video)),
+    ) {
+        let values = mapped.entry(name).or_default();
+        if !value.is_empty() {
+            values.push(value);
+        }
+    }
+    let mapped: BTreeMap<&str, String> = mapped
+        .into_iter()
+        .map(|(name, values)| (name, values.join(" ")))
+        .collect();
+
+    json!({
+        "deckName": options.deck,
+        "modelName": options.note_type,
+        "fields": mapped,
+        "tags": options.tags,
+        "options": {
+            "allowDuplicate": false,
+            "duplicateScope": "deck",
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SubtitleClip;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // Starts a stand-in for AnkiConnect on a random local port. Every request
+    // body is recorded, and `respond` produces the `result` or `error` for each
+    // action.
+    async fn fake_anki(
+        respond: fn(&Value) -> Result<Value, &'static str>,
+    ) -> (String, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    loop {
+                        let mut content_length = 0;
+                        let mut line = String::new();
+                        loop {
+                            line.clear();
+                            if stream.read_line(&mut line).await.unwrap() == 0 {
+                                return;
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                            let lower = line.to_ascii_lowercase();
+                            if let Some(length) = lower.strip_prefix("content-length:") {
+                                content_length = length.trim().parse().unwrap();
+                            }
+                        }
+
+                        let mut body = vec![0; content_length];
+                        stream.read_exact(&mut body).await.unwrap();
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+                        let response = match respond(&request) {
+                            Ok(result) => json!({ "result": result, "error": null }),
+                            Err(error) => json!({ "result": null, "error": error }),
+                        }
+                        .to_string();
+                        recorded.lock().unwrap().push(request);
+
+                        let reply = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                            response.len(),
+                            response
+                        );
+                        stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn generated_clip(index: usize, text: &str) -> GeneratedClip {
+        GeneratedClip {
+            clip: SubtitleClip {
+                index,
+                start_time: Duration::from_secs(index as u64),
+                end_time: Duration::from_secs(index as u64 + 1),
+                text: text.to_string(),
+                ..Default::default()
+            },
+            screenshot_path: PathBuf::from(format!("/out/screenshot_{}.png", index)),
+            audio_path: PathBuf::from(format!("/out/audio_clip_{}.mp3", index)),
+            video_path: None,
+        }
+    }
+
+    fn options() -> NoteOptions {
+        NoteOptions {
+            deck: "Mining".to_string(),
+            note_type: "Basic".to_string(),
+            tags: vec!["subs2srs".to_string()],
+            fields: FieldMapping {
+                sentence: "Front".to_string(),
+                meaning: "Front".to_string(),
+                audio: "Back".to_string(),
+                image: "Back".to_string(),
+                video: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_clips() {
+        let (url, requests) = fake_anki(|request| match request["action"].as_str().unwrap() {
+            "storeMediaFile" => Ok(request["params"]["filename"].clone()),
+            "canAddNotesWithErrorDetail" => Ok(json!([
+                { "canAdd": true },
+                { "canAdd": false, "error": "cannot create note because it is a duplicate" },
+                { "canAdd": false, "error": "model was not found: Basic" },
+                { "canAdd": true },
+            ])),
+            "addNotes" => Ok(json!([1001, null])),
+            action => panic!("unexpected action {}", action),
+        })
+        .await;
+
+        let mut clips = vec![
+            generated_clip(1, "一"),
+            generated_clip(2, "二"),
+            generated_clip(3, "三"),
+            generated_clip(4, "四"),
+        ];
+        clips[0].clip.meaning = "one".to_string();
+        let outcomes = AnkiConnect::new(url)
+            .push_clips(&clips, &options())
+            .await
+            .expect("failed to push clips");
+
+        assert_eq!(
+            outcomes,
+            [
+                NoteOutcome::Added(1001),
+                NoteOutcome::Duplicate,
+                NoteOutcome::Failed("model was not found: Basic".to_string()),
+                NoteOutcome::Failed("note was not added".to_string()),
+            ]
+        );
+
+        let requests = requests.lock().unwrap();
+        let actions: Vec<&str> = requests
+            .iter()
+            .map(|request| request["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(actions[..8], ["storeMediaFile"; 8]);
+        assert_eq!(actions[8..], ["canAddNotesWithErrorDetail", "addNotes"]);
+        assert!(requests.iter().all(|request| request["version"] == 6));
+
+        assert_eq!(
+            requests[0]["params"],
+            json!({ "filename": "audio_clip_1.mp3", "path": "/out/audio_clip_1.mp3" })
+        );
+        assert_eq!(
+            requests[1]["params"],
+            json!({ "filename": "screenshot_1.png", "path": "/out/screenshot_1.png" })
+        );
+
+        let added = &requests[9]["params"]["notes"];
+        assert_eq!(added.as_array().unwrap().len(), 2);
+        assert_eq!(
+            added[0],
+            json!({
+                "deckName": "Mining",
+                "modelName": "Basic",
+                "fields": {
+                    "Front": "一 one",
+                    "Back": "[sound:audio_clip_1.mp3] <img src=\"screenshot_1.png\">",
+                },
+                "tags": ["subs2srs"],
+                "options": { "allowDuplicate": false, "duplicateScope": "deck" },
+            })
+        );
+        assert_eq!(added[1]["fields"]["Front"], "四");
+    }
+
+    #[tokio::test]
+    async fn test_push_clips_reports_api_error() {
+        let (url, requests) = fake_anki(|_| Err("permission denied")).await;
+
+        let error = AnkiConnect::new(url)
+            .push_clips(&[generated_clip(1, "一")], &options())
+            .await
+            .expect_err("push should fail");
+
+        assert_eq!(
+            error.to_string(),
+            "AnkiConnect storeMediaFile failed: permission denied"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,503 @@
+use crate::GeneratedClip;
+use crate::note::{self, NoteFields};
+use rusqlite::{Connection, params};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+
+/// The separator Anki uses between the fields of a note.
+const FIELD_SEPARATOR: &str = "\x1f";
+
+/// The name of the note type created by `export_apkg`.
+pub(crate) const MODEL_NAME: &str = "subs2srs";
+const MODEL_CSS: &str = ".card { font-family: arial; font-size: 24px; text-align: center; }";
+const QUESTION_FORMAT: &str =
+    "{{#Video}}{{Video}}{{/Video}}{{^Video}}{{Image}}{{/Video}}<br>{{Audio}}";
+const ANSWER_FORMAT: &str = "{{FrontSide}}<hr id=answer>{{Sentence}}<br>{{Meaning}}";
+
+const SCHEMA: &str = "
+CREATE TABLE col (
+    id integer primary key, crt integer not null, mod integer not null,
+    scm integer not null, ver integer not null, dty integer not null,
+    usn integer not null, ls integer not null, conf text not null,
+    models text not null, decks text not null, dconf text not null,
+    tags text not null
+);
+CREATE TABLE notes (
+    id integer primary key, guid text not null, mid integer not null,
+    mod integer not null, usn integer not null, tags text not null,
+    flds text not null, sfld integer not null, csum integer not null,
+    flags integer not null, data text not null
+);
+CREATE TABLE cards (
+    id integer primary key, nid integer not null, did integer not null,
+    ord integer not null, mod integer not null, usn integer not null,
+    type integer not null, queue integer not null, due integer not null,
+    ivl integer not null, factor integer not null, reps integer not null,
+    lapses integer not null, left integer not null, odue integer not null,
+    odid integer not null, flags integer not null, data text not null
+);
+CREATE TABLE revlog (
+    id integer primary key, cid integer not null, usn integer not null,
+    ease integer not null, ivl integer not null, lastIvl integer not null,
+    factor integer not null, time integer not null, type integer not null
+);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+CREATE INDEX ix_notes_usn on notes (usn);
+CREATE INDEX ix_cards_usn on cards (usn);
+CREATE INDEX ix_revlog_usn on revlog (usn);
+CREATE INDEX ix_cards_nid on cards (nid);
+CREATE INDEX ix_cards_sched on cards (did, queue, due);
+CREATE INDEX ix_revlog_cid on revlog (cid);
+CREATE INDEX ix_notes_csum on notes (csum);
+";
+
+/// Writes an Anki `.apkg` package to `output` containing one note per clip.
+///
+/// Each note has a `Sentence` field with the subtitle text, a `Meaning` field
+/// with its paired translation, an `Audio` field referencing the clip's audio,
+/// an `Image` field referencing its screenshot, and a `Video` field referencing
+/// its video clip, if any. Cards show the video in place of the screenshot.
+/// The media files are copied into the package so it can be imported as-is.
+pub(crate) fn export_apkg(
+    deck_name: &str,
+    clips: &[GeneratedClip],
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if deck_name.is_empty() {
+        return Err("deck name is empty".into());
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let now_secs = now.as_secs() as i64;
+    let now_millis = now.as_millis() as i64;
+    let deck_id = stable_id(deck_name);
+    let model_id = stable_id(MODEL_NAME);
+
+    // The collection is built in a scratch file next to the output, since
+    // SQLite needs a real file to write to before we can zip it up.
+    let collection_path = output.with_extension("anki2.tmp");
+    if collection_path.exists() {
+        std::fs::remove_file(&collection_path)?;
+    }
+    let result = write_collection(
+        &collection_path,
+        deck_name,
+        deck_id,
+        model_id,
+        now_secs,
+        now_millis,
+        clips,
+    )
+    .and_then(|_| write_package(&collection_path, clips, output));
+    // A failure to clean up must not hide why the export failed.
+    let removed = std::fs::remove_file(&collection_path);
+    result?;
+    removed?;
+    Ok(())
+}
+
+fn write_collection(
+    path: &Path,
+    deck_name: &str,
+    deck_id: i64,
+    model_id: i64,
+    now_secs: i64,
+    now_millis: i64,
+    clips: &[GeneratedClip],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        params![
+            now_secs,
+            now_millis,
+            collection_conf(deck_id, model_id).to_string(),
+            models(deck_id, model_id, now_secs).to_string(),
+            decks(deck_name, deck_id, now_secs).to_string(),
+            deck_configs(now_secs).to_string(),
+        ],
+    )?;
+
+    for (position, generated) in clips.iter().enumerate() {
+        let fields = NoteFields::from_clip(generated)?;
+        let note_id = now_millis + position as i64;
+        let guid = note_guid(deck_name, generated);
+
+        tx.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, '', ?5, ?6, ?7, 0, '')",
+            params![
+                note_id,
+                guid,
+                model_id,
+                now_secs,
+                fields.values().join(FIELD_SEPARATOR),
+                fields.sentence,
+                field_checksum(&fields.sentence),
+            ],
+        )?;
+        tx.execute(
+            "INSERT INTO cards VALUES (?1, ?2, ?3, 0, ?4, -1, 0, 0, ?5, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+            params![note_id, note_id, deck_id, now_secs, position as i64 + 1],
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+fn write_package(
+    collection_path: &Path,
+    clips: &[GeneratedClip],
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zip = zip::ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default();
+
+    zip.start_file("collection.anki2", options)?;
+    zip.write_all(&std::fs::read(collection_path)?)?;
+
+    // Anki stores media in the package as numbered entries, with the `media`
+    // file mapping each number back to its original file name.
+    let mut media = BTreeMap::new();
+    let media_paths = clips.iter().flat_map(GeneratedClip::media_paths);
+    for (number, path) in media_paths.enumerate() {
+        zip.start_file(number.to_string(), options)?;
+        zip.write_all(&std::fs::read(path)?)?;
+        media.insert(number.to_string(), note::file_name(path)?);
+    }
+
+    zip.start_file("media", options)?;
+    zip.write_all(serde_json::to_string(&media)?.as_bytes())?;
+    zip.finish()?;
+
+    Ok(())
+}
+
+fn collection_conf(deck_id: i64, model_id: i64) -> serde_json::Value {
+    json!({
+        "activeDecks": [deck_id],
+        "curDeck": deck_id,
+        "curModel": model_id,
+        "nextPos": 1,
+        "estTimes": true,
+        "sortType": "noteFld",
+        "sortBackwards": false,
+        "timeLim": 0,
+        "addToCur": true,
+        "newSpread": 0,
+        "dueCounts": true,
+        "collapseTime": 1200,
+    })
+}
+
+fn models(deck_id: i64, model_id: i64, now_secs: i64) -> serde_json::Value {
+    let fields: Vec<serde_json::Value> = note::FIELD_NAMES
+        .iter()
+        .enumerate()
+        .map(|(ord, name)| {
+            json!({
+                "name": name,
+                "ord": ord,
+                "sticky": false,
+                "rtl": false,
+                "font": "Arial",
+                "size": 20,
+                "media": [],
+            })
+        })
+        .collect();
+
+    json!({
+        model_id.to_string(): {
+            "id": model_id,
+            "name": MODEL_NAME,
+            "type": 0,
+            "mod": now_secs,
+            "usn": -1,
+            "sortf": 0,
+            "did": deck_id,
+            "tmpls": [{
+                "name": "Card 1",
+                "ord": 0,
+                "qfmt": QUESTION_FORMAT,
+                "afmt": ANSWER_FORMAT,
+                "did": null,
+                "bqfmt": "",
+                "bafmt": "",
+            }],
+            "flds": fields,
+            "css": MODEL_CSS,
+            "latexPre": "\\documentclass[12pt]{article}\n\\begin{document}\n",
+            "latexPost": "\\end{document}",
+            "tags": [],
+            "vers": [],
+            "req": [[0, "any", [2, 3, 4]]],
+        }
+    })
+}
+
+fn decks(deck_name: &str, deck_id: i64, now_secs: i64) -> serde_json::Value {
+    let deck = |id: i64, name: &str| {
+        json!({
+            "id": id,
+            "name": name,
+            "desc": "",
+            "mod": now_secs,
+            "usn": -1,
+            "conf": 1,
+            "dyn": 0,
+            "collapsed": false,
+            "extendNew": 10,
+            "extendRev": 50,
+            "newToday": [0, 0],
+            "revToday": [0, 0],
+            "lrnToday": [0, 0],
+            "timeToday": [0, 0],
+        })
+    };
+
+    json!({
+        "1": deck(1, "Default"),
+        deck_id.to_string(): deck(deck_id, deck_name),
+    })
+}
+
+fn deck_configs(now_secs: i64) -> serde_json::Value {
+    json!({
+        "1": {
+            "id": 1,
+            "name": "Default",
+            "mod": now_secs,
+            "usn": -1,
+            "maxTaken": 60,
+            "autoplay": true,
+            "timer": 0,
+            "replayq": true,
+            "dyn": false,
+            "new": {
+                "delays": [1, 10],
+                "ints": [1, 4, 7],
+                "initialFactor": 2500,
+                "order": 1,
+                "perDay": 20,
+                "bury": true,
+                "separate": true,
+            },
+            "rev": {
+                "perDay": 100,
+                "ease4": 1.3,
+                "fuzz": 0.05,
+                "ivlFct": 1,
+                "maxIvl": 36500,
+                "bury": true,
+                "minSpace": 1,
+            },
+            "lapse": {
+                "delays": [10],
+                "mult": 0,
+                "minInt": 1,
+                "leechFails": 8,
+                "leechAction": 0,
+            },
+        }
+    })
+}
+
+// Derives an id from `name` so that re-exporting the same deck merges into it
+// on import rather than creating a duplicate deck or note type.
+fn stable_id(name: &str) -> i64 {
+    let digest = sha1_smol::Sha1::from(name).digest().bytes();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    // Anki ids must be positive and fit in a JavaScript number.
+    (u64::from_be_bytes(bytes) >> 12) as i64
+}
+
+// Derives the guid of the note for `generated` from the deck, its media and its
+// line, so that importing the deck again updates the notes instead of adding
+// duplicates.
+fn note_guid(deck_name: &str, generated: &GeneratedClip) -> String {
+    let media_stem = generated
+        .audio_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    format!(
+        "{:x}",
+        stable_id(&format!(
+            "{}{}{}{}{}",
+            deck_name, FIELD_SEPARATOR, media_stem, FIELD_SEPARATOR, generated.clip.index
+        ))
+    )
+}
+
+// Anki's duplicate-check checksum: the first 8 hex digits of the SHA-1 of the
+// sort field, interpreted as an integer.
+fn field_checksum(field: &str) -> i64 {
+    let digest = sha1_smol::Sha1::from(field).digest().bytes();
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&digest[..4]);
+    u32::from_be_bytes(bytes) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SubtitleClip;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const TEST_OUTPUT_DIR_BASE: &str = "/tmp/subs2srs_tests";
+
+    // Helper to create a unique test directory
+    fn setup_test_dir() -> PathBuf {
+        let test_run_id = Uuid::new_v4().to_string();
+        let output_dir = PathBuf::from(TEST_OUTPUT_DIR_BASE).join(test_run_id);
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir).unwrap();
+        }
+        fs::create_dir_all(&output_dir).unwrap();
+        output_dir
+    }
+
+    // Helper to create a clip with placeholder media files
+    fn generated_clip(dir: &Path, index: usize, text: &str) -> GeneratedClip {
+        let screenshot_path = dir.join(format!("screenshot_{}.png", index));
+        let audio_path = dir.join(format!("audio_clip_{}.mp3", index));
+        fs::write(&screenshot_path, format!("png {}", index)).unwrap();
+        fs::write(&audio_path, format!("mp3 {}", index)).unwrap();
+        GeneratedClip {
+            clip: SubtitleClip {
+                index,
+                start_time: Duration::from_secs(index as u64),
+                end_time: Duration::from_secs(index as u64 + 1),
+                text: text.to_string(),
+                ..Default::default()
+            },
+            screenshot_path,
+            audio_path,
+            video_path: None,
+        }
+    }
+
+    #[test]
+    fn test_export_apkg() {
+        let dir = setup_test_dir();
+        let clips = vec![
+            generated_clip(&dir, 1, "你好"),
+            generated_clip(&dir, 2, "第一行\n第二行"),
+        ];
+        let output = dir.join("deck.apkg");
+
+        export_apkg("Minecraft", &clips, &output).expect("failed to export apkg");
+        assert!(output.exists());
+        assert!(!output.with_extension("anki2.tmp").exists());
+
+        let mut archive = zip::ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut media = String::new();
+        archive
+            .by_name("media")
+            .unwrap()
+            .read_to_string(&mut media)
+            .unwrap();
+        let media: BTreeMap<String, String> = serde_json::from_str(&media).unwrap();
+        assert_eq!(media["0"], "audio_clip_1.mp3");
+        assert_eq!(media["1"], "screenshot_1.png");
+        assert_eq!(media["3"], "screenshot_2.png");
+
+        let mut screenshot = String::new();
+        archive
+            .by_name("3")
+            .unwrap()
+            .read_to_string(&mut screenshot)
+            .unwrap();
+        assert_eq!(screenshot, "png 2");
+
+        let collection_path = dir.join("collection.anki2");
+        let mut collection = File::create(&collection_path).unwrap();
+        std::io::copy(
+            &mut archive.by_name("collection.anki2").unwrap(),
+            &mut collection,
+        )
+        .unwrap();
+
+        let conn = Connection::open(&collection_path).unwrap();
+        let fields: Vec<String> = conn
+            .prepare("SELECT flds FROM notes ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "你好\x1f\x1f[sound:audio_clip_1.mp3]\x1f<img src=\"screenshot_1.png\">\x1f",
+                "第一行<br>第二行\x1f\x1f[sound:audio_clip_2.mp3]\x1f<img src=\"screenshot_2.png\">\x1f",
+            ]
+        );
+
+        let cards: i64 = conn
+            .query_row("SELECT COUNT(*) FROM cards", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(cards, 2);
+    }
+
+    #[test]
+    fn test_guids_are_stable() {
+        let dir = setup_test_dir();
+        let clips = vec![
+            generated_clip(&dir, 1, "你好"),
+            generated_clip(&dir, 2, "再见"),
+        ];
+        let guids = |name: &str| -> Vec<String> {
+            let output = dir.join(name);
+            export_apkg("Minecraft", &clips, &output).expect("failed to export apkg");
+            let mut archive = zip::ZipArchive::new(File::open(&output).unwrap()).unwrap();
+            let collection_path = output.with_extension("anki2");
+            std::io::copy(
+                &mut archive.by_name("collection.anki2").unwrap(),
+                &mut File::create(&collection_path).unwrap(),
+            )
+            .unwrap();
+            Connection::open(&collection_path)
+                .unwrap()
+                .prepare("SELECT guid FROM notes ORDER BY id")
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .map(|row| row.unwrap())
+                .collect()
+        };
+
+        // Exporting again gives the same notes, so importing it updates them.
+        let first = guids("first.apkg");
+        assert_eq!(first, guids("second.apkg"));
+        assert_ne!(first[0], first[1]);
+    }
+
+    #[test]
+    fn test_export_apkg_without_deck_name() {
+        let dir = setup_test_dir();
+        let output = dir.join("deck.apkg");
+        assert!(export_apkg("", &[], &output).is_err());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_field_checksum() {
+        // SHA-1 of "abc" starts with a9993e36.
+        assert_eq!(field_checksum("abc"), 0xa9993e36);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::SubtitleClip;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The `[Events]` columns assumed when an ASS file has no `Format:` line.
+const DEFAULT_ASS_FORMAT: &str =
+    "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+/// The `[Events]` columns assumed when an SSA file has no `Format:` line.
+const DEFAULT_SSA_FORMAT: &str =
+    "Marked, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+/// Parses the contents of an ASS or SSA file into clips.
+///
+/// Only `Dialogue:` events are kept. Override blocks such as `{\pos(10,10)}`
+/// and vector drawings are removed from the text, and `\N` becomes a line
+/// break. Each clip carries the event's style, actor and layer so callers can
+/// skip signs or karaoke. Clips are sorted by start time and numbered from 1.
+pub(crate) fn parse(contents: &str) -> Result<Vec<SubtitleClip>, Box<dyn std::error::Error>> {
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+
+    let mut section = String::new();
+    let mut script_info: BTreeMap<String, String> = BTreeMap::new();
+    let mut styles: Vec<String> = Vec::new();
+    let mut style_format: Vec<String> = Vec::new();
+    let mut event_format: Vec<String> = Vec::new();
+    let mut clips = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_ascii_lowercase();
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match (section.as_str(), key) {
+            ("script info", _) => {
+                script_info.insert(key.to_string(), value.to_string());
+            }
+            ("v4+ styles" | "v4 styles", "Format") => style_format = columns(value),
+            ("v4+ styles" | "v4 styles", "Style") => {
+                let name_column = style_format
+                    .iter()
+                    .position(|column| column == "name")
+                    .unwrap_or(0);
+                let name = value.split(',').nth(name_column).unwrap_or("").trim();
+                styles.push(style_name(name).to_string());
+            }
+            ("events", "Format") => event_format = columns(value),
+            ("events", "Dialogue") => {
+                if event_format.is_empty() {
+                    event_format = columns(default_event_format(&script_info));
+                }
+                let clip = parse_dialogue(value, &event_format, &styles)
+                    .map_err(|error| format!("line {}: {}", number, error))?;
+                if let Some(clip) = clip {
+                    clips.push(clip);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    clips.sort_by_key(|clip: &SubtitleClip| (clip.start_time, clip.end_time));
+    for (index, clip) in clips.iter_mut().enumerate() {
+        clip.index = index + 1;
+    }
+
+    Ok(clips)
+}
+
+fn default_event_format(script_info: &BTreeMap<String, String>) -> &'static str {
+    match script_info.get("ScriptType") {
+        Some(script_type) if script_type.eq_ignore_ascii_case("v4.00") => DEFAULT_SSA_FORMAT,
+        _ => DEFAULT_ASS_FORMAT,
+    }
+}
+
+fn columns(format: &str) -> Vec<String> {
+    format
+        .split(',')
+        .map(|column| column.trim().to_ascii_lowercase())
+        .collect()
+}
+
+// SSA files commonly prefix style names with `*`, which renderers ignore.
+fn style_name(name: &str) -> &str {
+    name.trim_start_matches('*')
+}
+
+// Parses the value of a `Dialogue:` line. Returns `None` for events with no
+// text left once override tags and drawings are removed.
+fn parse_dialogue(
+    value: &str,
+    format: &[String],
+    styles: &[String],
+) -> Result<Option<SubtitleClip>, String> {
+    // Text is always the last column and may itself contain commas.
+    let values: Vec<&str> = value.splitn(format.len(), ',').collect();
+    if values.len() != format.len() {
+        return Err(format!(
+            "expected {} columns but found {}",
+            format.len(),
+            values.len()
+        ));
+    }
+    let column = |name: &str| {
+        format
+            .iter()
+            .position(|column| column == name)
+            .map(|position| values[position].trim())
+    };
+
+    let start_time = parse_timestamp(column("start").ok_or("missing Start column")?)?;
+    let end_time = parse_timestamp(column("end").ok_or("missing End column")?)?;
+    let text = strip_overrides(column("text").ok_or("missing Text column")?);
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+
+    // Renderers fall back to the Default style for unknown style names.
+    let style = style_name(column("style").unwrap_or(""));
+    let style = if styles.is_empty() || styles.iter().any(|known| known == style) {
+        style
+    } else {
+        "Default"
+    };
+    let layer = match column("layer") {
+        Some(layer) => layer
+            .parse()
+            .map_err(|_| format!("invalid layer {:?}", layer))?,
+        None => 0,
+    };
+
+    Ok(Some(SubtitleClip {
+        index: 0,
+        start_time,
+        end_time,
+        text,
+        style: style.to_string(),
+        actor: column("name").unwrap_or("").to_string(),
+        layer,
+        ..Default::default()
+    }))
+}
+
+// Parses `H:MM:SS.cc`, where `cc` is hundredths of a second.
+fn parse_timestamp(timestamp: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid timestamp {:?}", timestamp);
+
+    let (clock, fraction) = timestamp.split_once('.').ok_or_else(invalid)?;
+    let parts = clock
+        .split(':')
+        .map(|part| part.parse::<u64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let [hours, minutes, seconds] = parts[..] else {
+        return Err(invalid());
+    };
+    if fraction.is_empty() || fraction.len() > 3 || minutes > 59 || seconds > 59 {
+        return Err(invalid());
+    }
+    // Pad to milliseconds, so `.5` and `.50` both mean 500ms.
+    let millis: u64 = format!("{:0<3}", fraction).parse().map_err(|_| invalid())?;
+
+    Ok(Duration::from_millis(
+        ((hours * 60 + minutes) * 60 + seconds) * 1000 + millis,
+    ))
+}
+
+// Removes `{...}` override blocks and drawings, and converts the `\N`, `\n`
+// and `\h` escapes into plain text.
+fn strip_overrides(text: &str) -> String {
+    let mut plain = String::new();
+    let mut drawing = false;
+    let mut rest = text;
+
+    while let Some(open) = rest.find('{') {
+        if !drawing {
+            plain.push_str(&rest[..open]);
+        }
+        let Some(close) = rest[open..].find('}') else {
+            rest = &rest[open..];
+            break;
+        };
+        // `\p1` and higher start a vector drawing, which lasts until `\p0`.
+        let block = &rest[open + 1..open + close];
+        for tag in block.split('\\') {
+            if let Some(scale) = tag.strip_prefix('p')
+                && let Ok(scale) = scale.trim().parse::<u32>()
+            {
+                drawing = scale > 0;
+            }
+        }
+        rest = &rest[open + close + 1..];
+    }
+    if !drawing {
+        plain.push_str(rest);
+    }
+
+    plain
+        .replace("\\N", "\n")
+        .replace("\\n", " ")
+        .replace("\\h", "\u{a0}")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "\u{feff}[Script Info]
+; A comment
+Title: Example
+ScriptType: v4.00+
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour
+Style: Default,Arial,20,&H00FFFFFF
+Style: Signs,Arial,20,&H00FFFFFF
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+Dialogue: 0,0:00:05.00,0:00:07.50,Default,Steve,0,0,0,,{\\i1}Hello,{\\i0} world\\Nsecond line
+Comment: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Not shown
+Dialogue: 1,0:00:01.00,0:00:03.00,Signs,,0,0,0,,{\\pos(100,200)\\fs40}村庄
+Dialogue: 0,0:00:08.00,0:00:09.00,Default,,0,0,0,,{\\p1}m 0 0 l 100 0 100 100{\\p0}
+Dialogue: 0,1:02:03.04,1:02:04.5,Missing,,0,0,0,,a\\hb
+";
+
+    #[test]
+    fn test_parse() {
+        let clips = parse(SCRIPT).expect("failed to parse");
+        assert_eq!(clips.len(), 3);
+
+        assert_eq!(clips[0].index, 1);
+        assert_eq!(clips[0].start_time, Duration::from_millis(1000));
+        assert_eq!(clips[0].end_time, Duration::from_millis(3000));
+        assert_eq!(clips[0].text, "村庄");
+        assert_eq!(clips[0].style, "Signs");
+        assert_eq!(clips[0].layer, 1);
+
+        assert_eq!(clips[1].index, 2);
+        assert_eq!(clips[1].start_time, Duration::from_millis(5000));
+        assert_eq!(clips[1].end_time, Duration::from_millis(7500));
+        assert_eq!(clips[1].text, "Hello, world\nsecond line");
+        assert_eq!(clips[1].style, "Default");
+        assert_eq!(clips[1].actor, "Steve");
+        assert_eq!(clips[1].layer, 0);
+
+        assert_eq!(clips[2].start_time, Duration::from_millis(3_723_040));
+        assert_eq!(clips[2].end_time, Duration::from_millis(3_724_500));
+        assert_eq!(clips[2].text, "a\u{a0}b");
+        assert_eq!(clips[2].style, "Default");
+    }
+
+    #[test]
+    fn test_parse_ssa() {
+        let script = "[Script Info]
+ScriptType: v4.00
+
+[V4 Styles]
+Format: Name, Fontname
+Style: *Default,Arial
+
+[Events]
+Dialogue: Marked=0,0:00:01.00,0:00:02.00,*Default,Alex,0000,0000,0000,,Hi
+";
+        let clips = parse(script).expect("failed to parse");
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].text, "Hi");
+        assert_eq!(clips[0].style, "Default");
+        assert_eq!(clips[0].actor, "Alex");
+        assert_eq!(clips[0].layer, 0);
+    }
+
+    #[test]
+    fn test_parse_invalid_timestamp() {
+        let script = "[Events]\nDialogue: 0,0:00,0:00:02.00,Default,,0,0,0,,Hi\n";
+        let error = parse(script).unwrap_err();
+        assert_eq!(error.to_string(), "line 2: invalid timestamp \"0:00\"");
+    }
+}
@@ -1,36 +1,253 @@
+use crate::SubtitleClip;
+use crate::error::Error;
 use crate::ffmpeg::FFmpegBuilder;
+use std::str::FromStr;
 use std::sync::mpsc::Sender;
-use tokio::process::Command;
 
+/// PaddingOptions widens the audio of each clip beyond its subtitle timing,
+/// since subtitles are often cut a little early or late. By default there is
+/// no padding, so clips are cut exactly at the subtitle times.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaddingOptions {
+    /// Seconds of audio kept before the line starts.
+    pub lead_in: f64,
+    /// Seconds of audio kept after the line ends.
+    pub lead_out: f64,
+    /// Stops the padding at the neighbouring lines, so their audio is not included.
+    pub avoid_neighbours: bool,
+    /// Moves each boundary out to the nearest silence found by `silencedetect`.
+    pub snap_to_silence: bool,
+}
+
+impl Default for PaddingOptions {
+    fn default() -> Self {
+        PaddingOptions {
+            lead_in: 0.0,
+            lead_out: 0.0,
+            avoid_neighbours: true,
+            snap_to_silence: false,
+        }
+    }
+}
+
+/// AudioRange is a span of the input, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// AudioCodec is the format audio clips are encoded in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AudioCodec {
+    #[default]
+    Mp3,
+    /// Opus in an Ogg container, the smallest for speech.
+    Opus,
+    /// AAC in an MP4 container.
+    Aac,
+    /// Lossless, so the bitrate is ignored.
+    Flac,
+}
+
+impl AudioCodec {
+    pub const ALL: [AudioCodec; 4] = [
+        AudioCodec::Mp3,
+        AudioCodec::Opus,
+        AudioCodec::Aac,
+        AudioCodec::Flac,
+    ];
+
+    /// The extension of the files written with this codec, without a dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioCodec::Mp3 => "mp3",
+            AudioCodec::Opus => "ogg",
+            AudioCodec::Aac => "m4a",
+            AudioCodec::Flac => "flac",
+        }
+    }
+
+    /// The name of the FFmpeg encoder.
+    pub fn encoder(self) -> &'static str {
+        match self {
+            AudioCodec::Mp3 => "libmp3lame",
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Aac => "aac",
+            AudioCodec::Flac => "flac",
+        }
+    }
+
+    /// A bitrate in kbit/s that keeps speech clear in this codec.
+    pub fn default_bitrate(self) -> u32 {
+        match self {
+            AudioCodec::Mp3 => 192,
+            AudioCodec::Opus => 64,
+            AudioCodec::Aac => 128,
+            AudioCodec::Flac => 0,
+        }
+    }
+
+    pub fn is_lossless(self) -> bool {
+        self == AudioCodec::Flac
+    }
+}
+
+impl std::fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AudioCodec::Mp3 => "mp3",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Aac => "aac",
+            AudioCodec::Flac => "flac",
+        })
+    }
+}
+
+impl FromStr for AudioCodec {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        AudioCodec::ALL
+            .into_iter()
+            .find(|codec| codec.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                format!(
+                    "unknown audio codec {:?}, expected mp3, opus, aac or flac",
+                    name
+                )
+            })
+    }
+}
+
+/// AudioEncoding is how audio clips are encoded. The default is constant
+/// 192 kbit/s MP3.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioEncoding {
+    pub codec: AudioCodec,
+    /// The bitrate in kbit/s. Ignored by FLAC, and by MP3 with `mp3_vbr_quality`.
+    pub bitrate: u32,
+    /// Encodes MP3 with a variable bitrate of this LAME quality instead, from
+    /// 0 (best) to 9 (smallest).
+    pub mp3_vbr_quality: Option<u8>,
+    /// Resamples the audio to this many Hz, instead of keeping the input's rate.
+    pub sample_rate: Option<u32>,
+    /// Mixes the audio down to a single channel.
+    pub mono: bool,
+}
+
+impl AudioEncoding {
+    /// The encoding for `codec` at its default bitrate.
+    pub fn new(codec: AudioCodec) -> Self {
+        AudioEncoding {
+            codec,
+            bitrate: codec.default_bitrate(),
+            mp3_vbr_quality: None,
+            sample_rate: None,
+            mono: false,
+        }
+    }
+}
+
+impl Default for AudioEncoding {
+    fn default() -> Self {
+        AudioEncoding::new(AudioCodec::Mp3)
+    }
+}
+
+/// Pads the timing of each clip, returning the range to record along with the
+/// bounds it may later be widened to.
+///
+/// Ranges never start before 0 or end after `duration`, when it is known.
+/// With `avoid_neighbours`, they also stop where the previous line ends and
+/// the next line starts, unless the lines already overlap.
+pub(crate) fn padded_ranges(
+    clips: &[SubtitleClip],
+    options: &PaddingOptions,
+    duration: Option<f64>,
+) -> Vec<(AudioRange, AudioRange)> {
+    let mut by_start: Vec<usize> = (0..clips.len()).collect();
+    by_start.sort_by_key(|&position| (clips[position].start_time, clips[position].end_time));
+    let mut neighbours = vec![(None, None); clips.len()];
+    for (order, &position) in by_start.iter().enumerate() {
+        let previous = order.checked_sub(1).map(|order| &clips[by_start[order]]);
+        let next = by_start.get(order + 1).map(|&position| &clips[position]);
+        neighbours[position] = (previous, next);
+    }
+
+    clips
+        .iter()
+        .zip(neighbours)
+        .map(|(clip, (previous, next))| {
+            let start = clip.start_time.as_secs_f64();
+            let end = clip.end_time.as_secs_f64();
+            let mut bounds = AudioRange {
+                start: 0.0,
+                end: duration.unwrap_or(f64::INFINITY).max(end),
+            };
+            if options.avoid_neighbours {
+                if let Some(previous) = previous {
+                    bounds.start = bounds.start.max(previous.end_time.as_secs_f64().min(start));
+                }
+                if let Some(next) = next {
+                    bounds.end = bounds.end.min(next.start_time.as_secs_f64().max(end));
+                }
+            }
+
+            let range = AudioRange {
+                start: (start - options.lead_in.max(0.0)).max(bounds.start),
+                end: (end + options.lead_out.max(0.0)).min(bounds.end),
+            };
+            (range, bounds)
+        })
+        .collect()
+}
+
+/// Records the input from `start_time` to `end_time` as `output_stem` with the
+/// extension of `encoding` added, and returns the path of the file written.
+/// Without a `stream`, ffmpeg picks the input's default audio stream.
 pub async fn record_audio_clip(
     start_time: f64,
     end_time: f64,
     input: String,
-    output: String,
+    output_stem: String,
+    encoding: AudioEncoding,
+    stream: Option<u32>,
     progress: Sender<u32>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    assert!(start_time >= 0.0);
-    assert!(end_time > start_time);
-    assert_ne!(input, "");
-    assert_ne!(output, "");
+) -> Result<String, Error> {
+    if end_time <= start_time {
+        return Err(Error::InvalidTimeRange {
+            start: start_time,
+            end: end_time,
+        });
+    }
 
-    let ffmpeg = FFmpegBuilder::new(input, output)
+    // The stem may contain dots, so the extension is appended rather than set.
+    let output = format!("{}.{}", output_stem, encoding.codec.extension());
+    let mut builder = audio_clip_builder(input, output.clone(), encoding)
         .seek_to(start_time)
-        .end_at(end_time)
-        .disable_video()
-        .encode_mp3_audio()
-        .build();
+        .end_at(end_time);
+    if let Some(stream) = stream {
+        builder = builder.map_audio_stream(stream);
+    }
+    builder.build()?.run().await?;
 
-    Command::new("ffmpeg")
-        .args(ffmpeg.args())
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .output()
-        .await?;
+    // Progress is best-effort, so a listener that has gone away is ignored.
+    let _ = progress.send(1);
 
-    progress.send(1).expect("failed to send progress");
+    Ok(output)
+}
 
-    Ok(())
+// Sets up everything about an audio clip but which part of the input it covers.
+pub(crate) fn audio_clip_builder(
+    input: String,
+    output: String,
+    encoding: AudioEncoding,
+) -> FFmpegBuilder {
+    FFmpegBuilder::new(input, output)
+        .overwrite()
+        .disable_video()
+        .encode_audio(encoding)
 }
 
 #[cfg(test)]
@@ -68,14 +285,23 @@ mod tests {
         let start_time = 10.10;
         let end_time = 12.12;
         let input = get_absolute_path(TEST_VIDEO);
-        let output = format!("{}/audio_clip.mp3", setup_test_dir().to_str().unwrap());
-        let output_path = PathBuf::from(&output);
+        let output = format!("{}/audio_clip", setup_test_dir().to_str().unwrap());
+        let output_path = PathBuf::from(format!("{}.mp3", output));
         let (tx, rx) = std::sync::mpsc::channel();
 
         assert!(!output_path.exists());
-        record_audio_clip(start_time, end_time, input, output, tx)
-            .await
-            .expect("failed to record audio clip");
+        let written = record_audio_clip(
+            start_time,
+            end_time,
+            input,
+            output,
+            AudioEncoding::default(),
+            None,
+            tx,
+        )
+        .await
+        .expect("failed to record audio clip");
+        assert_eq!(PathBuf::from(written), output_path);
         assert!(output_path.exists());
 
         let ffmpeg_command_executions: u32 = rx.iter().sum();
@@ -84,4 +310,112 @@ mod tests {
             "one clip should have been converted with ffmpeg"
         );
     }
+
+    fn clip(start_ms: u64, end_ms: u64) -> SubtitleClip {
+        SubtitleClip {
+            start_time: std::time::Duration::from_millis(start_ms),
+            end_time: std::time::Duration::from_millis(end_ms),
+            ..Default::default()
+        }
+    }
+
+    fn range(start: f64, end: f64) -> AudioRange {
+        AudioRange { start, end }
+    }
+
+    #[test]
+    fn test_padded_ranges() {
+        // The last two lines overlap, and the first starts right at 0.
+        let clips = [
+            clip(100, 1000),
+            clip(5000, 6000),
+            clip(1100, 3000),
+            clip(2900, 4000),
+        ];
+        // Without padding, clips are cut exactly at the subtitle times.
+        let (unpadded, _) = padded_ranges(&clips, &PaddingOptions::default(), Some(6.2))[1];
+        assert_eq!(unpadded, range(5.0, 6.0));
+
+        let options = PaddingOptions {
+            lead_in: 0.5,
+            lead_out: 0.5,
+            ..Default::default()
+        };
+
+        let ranges: Vec<AudioRange> = padded_ranges(&clips, &options, Some(6.2))
+            .into_iter()
+            .map(|(range, _)| range)
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                range(0.0, 1.1),
+                range(4.5, 6.2),
+                range(1.0, 3.0),
+                range(2.9, 4.5)
+            ]
+        );
+
+        let options = PaddingOptions {
+            avoid_neighbours: false,
+            ..options
+        };
+        let (range, bounds) = padded_ranges(&clips, &options, None)[2];
+        assert!((range.start - 0.6).abs() < 1e-9 && range.end == 3.5);
+        assert_eq!(
+            bounds,
+            AudioRange {
+                start: 0.0,
+                end: f64::INFINITY
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_audio_invalid_time_range() {
+        let input = get_absolute_path(TEST_VIDEO);
+        let output = format!("{}/audio_clip", setup_test_dir().to_str().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let result = record_audio_clip(
+            12.0,
+            10.0,
+            input,
+            output,
+            AudioEncoding::default(),
+            None,
+            tx,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::InvalidTimeRange {
+                start: 12.0,
+                end: 10.0
+            })
+        ));
+        assert_eq!(rx.iter().sum::<u32>(), 0, "failed clips report no progress");
+    }
+
+    #[test]
+    fn test_audio_clip_builder_overwrites() {
+        let args = audio_clip_builder(
+            "in.mkv".to_string(),
+            "out.mp3".to_string(),
+            AudioEncoding::default(),
+        )
+        .build()
+        .unwrap()
+        .args();
+        assert!(args.iter().any(|arg| arg == "-y"), "{:?}", args);
+    }
+
+    #[test]
+    fn test_audio_codec_from_str() {
+        for codec in AudioCodec::ALL {
+            assert_eq!(codec.to_string().parse(), Ok(codec));
+        }
+        assert_eq!("OPUS".parse(), Ok(AudioCodec::Opus));
+        assert!("wav".parse::<AudioCodec>().is_err());
+    }
 }
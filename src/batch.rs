@@ -0,0 +1,363 @@
+use crate::condensed::{CondensedOptions, record_condensed_audio};
+use crate::error::Error;
+use crate::naming::MediaNaming;
+use crate::scheduler::{self, ClipEvent};
+use crate::{ClipOptions, GeneratedClip, SubtitleClip, probe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+/// The extensions of the files picked up from a directory of videos.
+pub const VIDEO_EXTENSIONS: &[&str] = &["mkv", "mp4", "m4v", "webm", "avi", "mov", "ts"];
+/// The extensions of the files picked up from a directory of subtitles.
+pub const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "vtt", "ass", "ssa"];
+
+/// How similar two file names must be to be matched when their episode
+/// numbers do not decide it.
+const MIN_SIMILARITY: f64 = 0.5;
+
+/// Episode is a video along with the subtitle file matched to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Episode {
+    pub number: Option<u32>,
+    pub video: PathBuf,
+    /// `None` if no subtitle file matched, in which case the episode is skipped.
+    pub subtitle: Option<PathBuf>,
+}
+
+/// EpisodeJob is the clips of one video, ready to be generated.
+pub(crate) struct EpisodeJob {
+    pub video_path: String,
+    pub naming: MediaNaming,
+    pub clips: Vec<SubtitleClip>,
+    /// The language of the subtitles, used to pick the audio stream.
+    pub language: Option<String>,
+}
+
+/// Lists the files in `dir` with one of `extensions`, sorted by name.
+pub fn list_files(dir: &Path, extensions: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        if path.is_file() && extension.is_some_and(|extension| extensions.contains(&&*extension)) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Matches each video with a subtitle file, by episode number when both
+/// names have one and otherwise by how similar the names are. Each subtitle
+/// file is used at most once.
+pub fn match_episodes(videos: &[PathBuf], subtitles: &[PathBuf]) -> Vec<Episode> {
+    let stem = |path: &PathBuf| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    let subtitle_numbers: Vec<Option<u32>> = subtitles
+        .iter()
+        .map(|subtitle| episode_number(&stem(subtitle)))
+        .collect();
+    let mut used = vec![false; subtitles.len()];
+
+    let mut episodes: Vec<Episode> = videos
+        .iter()
+        .map(|video| Episode {
+            number: episode_number(&stem(video)),
+            video: video.clone(),
+            subtitle: None,
+        })
+        .collect();
+
+    // Episode numbers first, so a similar name cannot take another episode's subtitle.
+    for episode in episodes
+        .iter_mut()
+        .filter(|episode| episode.number.is_some())
+    {
+        let best = (0..subtitles.len())
+            .filter(|&position| !used[position] && subtitle_numbers[position] == episode.number)
+            .max_by(|&a, &b| {
+                let video = stem(&episode.video);
+                similarity(&video, &stem(&subtitles[a]))
+                    .total_cmp(&similarity(&video, &stem(&subtitles[b])))
+            });
+        if let Some(position) = best {
+            used[position] = true;
+            episode.subtitle = Some(subtitles[position].clone());
+        }
+    }
+
+    for episode in episodes
+        .iter_mut()
+        .filter(|episode| episode.subtitle.is_none())
+    {
+        let video = stem(&episode.video);
+        let best = (0..subtitles.len())
+            .filter(|&position| !used[position])
+            .map(|position| (position, similarity(&video, &stem(&subtitles[position]))))
+            .filter(|(_, similarity)| *similarity >= MIN_SIMILARITY)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((position, _)) = best {
+            used[position] = true;
+            episode.subtitle = Some(subtitles[position].clone());
+        }
+    }
+
+    episodes
+}
+
+/// Guesses the episode number from a file name such as `Show.S01E03.1080p`,
+/// `Show EP03`, `節目 第3集`, `[Group] Show - 03 [1080p]` or `Show 03`.
+///
+/// Numbers that look like resolutions, codecs or bit depths are ignored.
+pub fn episode_number(name: &str) -> Option<u32> {
+    // Treat full width digits like `３` as ASCII.
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '０'..='９' => char::from_digit(c as u32 - '０' as u32, 10).unwrap(),
+            c => c,
+        })
+        .collect();
+    let lower = name.to_lowercase();
+    let numbers = numbers(&lower);
+
+    let preceded_by = |begin: usize, prefixes: &[&str]| {
+        let before = &lower[..begin];
+        prefixes.iter().any(|prefix| {
+            before.ends_with(prefix)
+                && !before[..before.len() - prefix.len()]
+                    .chars()
+                    .next_back()
+                    .is_some_and(char::is_alphabetic)
+        })
+    };
+    let followed_by = |end: usize, suffixes: &[&str]| {
+        suffixes
+            .iter()
+            .any(|suffix| lower[end..].starts_with(suffix))
+    };
+
+    let season_episode = numbers.iter().find(|(begin, _, _)| {
+        let Some(season) = lower[..*begin].strip_suffix('e') else {
+            return false;
+        };
+        let season = season.trim_end_matches(' ');
+        let rest = season.trim_end_matches(|c: char| c.is_ascii_digit());
+        rest.len() < season.len() && rest.ends_with('s')
+    });
+    let chinese = numbers.iter().find(|(begin, end, _)| {
+        lower[..*begin].ends_with('第') && followed_by(*end, &["集", "話", "话", "回"])
+    });
+    let prefixed = numbers
+        .iter()
+        .find(|(begin, _, _)| preceded_by(*begin, &["ep", "ep.", "ep ", "e", "#"]));
+    let dashed = numbers
+        .iter()
+        .find(|(begin, _, _)| lower[..*begin].ends_with("- "));
+    let last = numbers.iter().rev().find(|(begin, end, _)| {
+        !followed_by(*end, &["p", "i ", "bit", "k", "x"]) && !preceded_by(*begin, &["x", "h", "x."])
+    });
+
+    season_episode
+        .or(chinese)
+        .or(prefixed)
+        .or(dashed)
+        .or(last)
+        .map(|(_, _, number)| *number)
+}
+
+// Finds the runs of ASCII digits in `text`, as `(begin, end, value)`.
+fn numbers(text: &str) -> Vec<(usize, usize, u32)> {
+    let mut numbers = Vec::new();
+    let mut start = None;
+    for (position, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_ascii_digit(), start) {
+            (true, None) => start = Some(position),
+            (false, Some(begin)) => {
+                if let Ok(number) = text[begin..position].parse() {
+                    numbers.push((begin, position, number));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    numbers
+}
+
+// The Dice coefficient of the character pairs in `a` and `b`, ignoring case.
+fn similarity(a: &str, b: &str) -> f64 {
+    let pairs = |text: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = text.to_lowercase().chars().collect();
+        chars.windows(2).map(|pair| (pair[0], pair[1])).collect()
+    };
+    let a = pairs(a);
+    let mut b = pairs(b);
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let total = a.len() + b.len();
+    let mut shared = 0;
+    for pair in a {
+        if let Some(position) = b.iter().position(|other| *other == pair) {
+            b.swap_remove(position);
+            shared += 1;
+        }
+    }
+    2.0 * shared as f64 / total as f64
+}
+
+/// Generates the clips of each job in turn, reporting every clip to `events`.
+/// With `condensed`, each job's condensed audio is recorded after its clips,
+/// in the same encoding.
+///
+/// Unless `options` picks an audio stream, each job records the stream in
+/// the language of its subtitles, if there is one.
+///
+/// Returns one result per clip, in the order of `jobs` and their clips.
+pub(crate) async fn process_episodes(
+    jobs: Vec<EpisodeJob>,
+    output_dir: String,
+    options: ClipOptions,
+    concurrency: usize,
+    condensed: Option<CondensedOptions>,
+    events: Sender<ClipEvent>,
+) -> Vec<Result<GeneratedClip, Error>> {
+    let mut results = Vec::new();
+    for job in jobs {
+        let mut options = options.clone();
+        if options.audio_stream.is_none()
+            && let Some(language) = &job.language
+            && let Ok(streams) = probe::audio_streams(job.video_path.clone()).await
+        {
+            options.audio_stream = probe::preferred_audio_stream(&streams, language);
+        }
+        results.extend(
+            scheduler::process_clips(
+                job.video_path.clone(),
+                output_dir.clone(),
+                &job.naming,
+                options.clone(),
+                job.clips.clone(),
+                concurrency,
+                events.clone(),
+            )
+            .await,
+        );
+
+        if let Some(condensed) = condensed {
+            let output = Path::new(&output_dir).join(format!(
+                "{}.{}",
+                job.naming.condensed_stem(),
+                options.encoding.codec.extension()
+            ));
+            let result = record_condensed_audio(
+                &job.clips,
+                job.video_path.clone(),
+                output,
+                condensed,
+                options.encoding,
+                options.audio_stream,
+            )
+            .await;
+            let _ = events.send(ClipEvent::Condensed {
+                video_path: job.video_path,
+                result: result.map_err(|error| error.to_string()),
+            });
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use uuid::Uuid;
+
+    const TEST_OUTPUT_DIR_BASE: &str = "/tmp/subs2srs_tests";
+
+    // Helper to create a unique test directory
+    fn setup_test_dir() -> PathBuf {
+        let test_run_id = Uuid::new_v4().to_string();
+        let output_dir = PathBuf::from(TEST_OUTPUT_DIR_BASE).join(test_run_id);
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir).unwrap();
+        }
+        fs::create_dir_all(&output_dir).unwrap();
+        output_dir
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_episode_number() {
+        assert_eq!(episode_number("Show.S01E03.1080p.x264"), Some(3));
+        assert_eq!(episode_number("Show S2 E11"), Some(11));
+        assert_eq!(episode_number("節目 第3集"), Some(3));
+        assert_eq!(episode_number("アニメ 第１２話"), Some(12));
+        assert_eq!(episode_number("[Group] Show 2 - 03 [1080p]"), Some(3));
+        assert_eq!(episode_number("Show EP07 720p"), Some(7));
+        assert_eq!(episode_number("Show 05 (BD 1920x1080 HEVC 10bit)"), Some(5));
+        assert_eq!(episode_number("Minecraft_1.20生存#1.偏頭"), Some(1));
+        assert_eq!(episode_number("Show 07.ja"), Some(7));
+        assert_eq!(episode_number("Movie"), None);
+    }
+
+    #[test]
+    fn test_match_episodes_by_number() {
+        let videos = paths(&[
+            "/v/[Group] Show - 01 [1080p].mkv",
+            "/v/[Group] Show - 02 [1080p].mkv",
+            "/v/[Group] Show - 03 [1080p].mkv",
+        ]);
+        let subtitles = paths(&["/s/Show S01E02.ja.srt", "/s/Show S01E01.ja.srt"]);
+
+        let episodes = match_episodes(&videos, &subtitles);
+        assert_eq!(
+            episodes
+                .iter()
+                .map(|episode| (episode.number, episode.subtitle.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (Some(1), Some(subtitles[1].clone())),
+                (Some(2), Some(subtitles[0].clone())),
+                (Some(3), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_match_episodes_by_name() {
+        let videos = paths(&["/v/The Pilot.mkv", "/v/Finale.mkv"]);
+        let subtitles = paths(&["/s/finale.ass", "/s/the pilot.zh.ass", "/s/extras.ass"]);
+
+        let episodes = match_episodes(&videos, &subtitles);
+        assert_eq!(episodes[0].subtitle, Some(subtitles[1].clone()));
+        assert_eq!(episodes[1].subtitle, Some(subtitles[0].clone()));
+    }
+
+    #[test]
+    fn test_list_files() {
+        let dir = setup_test_dir();
+        fs::create_dir_all(dir.join("nested.mkv")).unwrap();
+        for name in ["b.MKV", "a.mp4", "notes.txt", "a.srt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let videos = list_files(&dir, VIDEO_EXTENSIONS).unwrap();
+        assert_eq!(videos, [dir.join("a.mp4"), dir.join("b.MKV")]);
+        let subtitles = list_files(&dir, SUBTITLE_EXTENSIONS).unwrap();
+        assert_eq!(subtitles, [dir.join("a.srt")]);
+    }
+}
@@ -0,0 +1,694 @@
+use crate::ClipOptions;
+use crate::audio::{AudioCodec, AudioEncoding, PaddingOptions};
+use crate::batch::{self, EpisodeJob};
+use crate::condensed::CondensedOptions;
+use crate::naming::{self, MediaNaming, NamingTemplate};
+use crate::pairing::{PairingOptions, pair_subtitles};
+use crate::scheduler::{self, ClipEvent};
+use crate::screenshot::{self, ImageFormat, ImageOptions, ImageSize, SubtitleOverlay};
+use crate::sync::{self, SyncOptions};
+use crate::timing::{self, FrameRateConversion, Stretch, TimingOptions};
+use crate::video::{VideoFormat, VideoOptions};
+use crate::{SubtitleClip, deck_name_for, export_deck, extraction, probe, subtitles};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Cli is the command line of `subs2srs-rs`. Without a subcommand the GUI is
+/// started instead.
+#[derive(Debug, Parser)]
+#[command(version, about = "Turn videos and subtitles into Anki cards")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Generates a screenshot and audio clip for each subtitle line, and
+    /// exports them as an Anki deck.
+    Generate(GenerateArgs),
+    /// Matches a directory of videos with a directory of subtitles by episode,
+    /// and generates every matched episode into one deck.
+    Batch(BatchArgs),
+    /// Describes a video and lists its audio tracks, subtitle tracks and chapters.
+    Probe { video: String },
+    /// Prints the lines of a subtitle file as tab separated values, corrected
+    /// by any timing flags to preview them.
+    ListSubs {
+        subtitle: String,
+        /// A subtitle file in your native language to pair with each line.
+        #[arg(long)]
+        native: Option<String>,
+        #[command(flatten)]
+        timing: TimingArgs,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct GenerateArgs {
+    #[arg(long)]
+    pub video: String,
+    #[arg(long)]
+    pub subtitle: String,
+    /// A subtitle file in your native language to pair with each line.
+    #[arg(long)]
+    pub native: Option<String>,
+    #[command(flatten)]
+    pub timing: TimingArgs,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+/// TimingArgs correct the times of a subtitle file. Without any, the timing
+/// saved for the file is used.
+#[derive(Debug, clap::Args)]
+pub struct TimingArgs {
+    /// Shifts every line by this many seconds, or by a time like -00:00:01.500.
+    #[arg(long, allow_hyphen_values = true, value_parser = timing::parse_time)]
+    pub shift: Option<f64>,
+    /// Moves two lines to where they start in the video, stretching the rest
+    /// to match, as LINE=TIME,LINE=TIME.
+    #[arg(long)]
+    pub stretch: Option<Stretch>,
+    /// Converts subtitles made for a video at one frame rate to another, as
+    /// FROM:TO, e.g. 25:23.976 for a PAL release.
+    #[arg(long)]
+    pub frame_rate: Option<FrameRateConversion>,
+    /// Saves the timing flags next to the subtitle file, where later runs
+    /// and the GUI pick them up.
+    #[arg(long)]
+    pub save_timing: bool,
+}
+
+impl TimingArgs {
+    // The timing given on the command line, or else the timing saved for `subtitle`.
+    fn options(&self, subtitle: &str) -> Result<TimingOptions, String> {
+        if self.shift.is_none() && self.stretch.is_none() && self.frame_rate.is_none() {
+            if self.save_timing {
+                return Err(
+                    "--save-timing needs --shift, --stretch or --frame-rate to save".to_string(),
+                );
+            }
+            return TimingOptions::load(subtitle).map_err(|error| error.to_string());
+        }
+        let options = TimingOptions {
+            offset: self.shift.unwrap_or(0.0),
+            stretch: self.stretch,
+            frame_rate: self.frame_rate,
+        };
+        if self.save_timing {
+            options
+                .save(subtitle)
+                .map_err(|error| format!("unable to save the timing: {}", error))?;
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct BatchArgs {
+    /// A directory of episodes.
+    #[arg(long)]
+    pub videos: PathBuf,
+    /// A directory of subtitle files, one per episode.
+    #[arg(long)]
+    pub subtitles: PathBuf,
+    /// Only prints how the videos and subtitles were matched.
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+/// OutputArgs are the options shared by `generate` and `batch`.
+#[derive(Debug, clap::Args)]
+pub struct OutputArgs {
+    /// The directory to write the media and the deck to.
+    #[arg(long, short)]
+    pub output: PathBuf,
+    /// The deck name, defaulting to the name of the video or video directory.
+    #[arg(long)]
+    pub deck: Option<String>,
+    /// How to name the media files. Supports {deck}, {episode}, {index:04},
+    /// {start}, {end} and {video_stem}.
+    #[arg(long, default_value = naming::DEFAULT_TEMPLATE)]
+    pub name_template: String,
+    /// The number of clips to generate at once.
+    #[arg(long, short, default_value_t = scheduler::default_concurrency())]
+    pub jobs: usize,
+    /// Skips lines with this ASS/SSA style. Can be given more than once.
+    #[arg(long = "exclude-style")]
+    pub excluded_styles: Vec<String>,
+    /// Seconds of audio kept before each line.
+    #[arg(long, default_value_t = PaddingOptions::default().lead_in)]
+    pub lead_in: f64,
+    /// Seconds of audio kept after each line.
+    #[arg(long, default_value_t = PaddingOptions::default().lead_out)]
+    pub lead_out: f64,
+    /// Lets the padding run into the neighbouring lines.
+    #[arg(long)]
+    pub allow_overlap: bool,
+    /// Moves the start and end of each clip out to the nearest silence.
+    #[arg(long)]
+    pub snap_to_silence: bool,
+    /// The audio format: mp3, opus, aac or flac.
+    #[arg(long, default_value_t = AudioCodec::Mp3)]
+    pub audio_codec: AudioCodec,
+    /// The audio bitrate in kbit/s, defaulting to 192 for MP3, 64 for Opus
+    /// and 128 for AAC.
+    #[arg(long)]
+    pub audio_bitrate: Option<u32>,
+    /// Encodes MP3 with a variable bitrate of this quality, from 0 (best) to 9.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub mp3_vbr_quality: Option<u8>,
+    /// Resamples the audio to this many Hz.
+    #[arg(long)]
+    pub sample_rate: Option<u32>,
+    /// Mixes the audio down to one channel.
+    #[arg(long)]
+    pub mono: bool,
+    /// Extracts this many lines per ffmpeg command, 20 if no number is given,
+    /// reading the video once per group of lines instead of once per file.
+    #[arg(long, value_name = "LINES")]
+    pub single_pass: Option<Option<usize>>,
+    /// The audio track to record, numbered as listed by `probe`. Defaults to
+    /// the track in the subtitle's language, if the file name gives one.
+    #[arg(long)]
+    pub audio_stream: Option<u32>,
+    /// Shifts the lines of each episode onto the speech in its audio.
+    #[arg(long)]
+    pub sync: bool,
+    /// Lets the shift change partway through an episode, as when a scene was
+    /// cut, whenever that aligns this many more seconds of speech.
+    #[arg(long, value_name = "SECONDS", requires = "sync")]
+    pub sync_splits: Option<f64>,
+    /// The largest shift --sync tries in either direction, in seconds.
+    #[arg(long, default_value_t = SyncOptions::default().max_offset, requires = "sync")]
+    pub max_sync_offset: f64,
+    /// The screenshot format: png, jpeg, webp or avif.
+    #[arg(long, default_value_t = ImageFormat::Png)]
+    pub image_format: ImageFormat,
+    /// The screenshot quality from 1 to 100. Ignored by PNG.
+    #[arg(long, default_value_t = ImageOptions::default().quality,
+          value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub image_quality: u8,
+    /// Scales screenshots to this width. With --image-height, screenshots are
+    /// shrunk to fit within both instead.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub image_width: Option<u32>,
+    /// Scales screenshots to this height, 320 unless --image-width is given.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub image_height: Option<u32>,
+    /// Keeps screenshots at the size of the video.
+    #[arg(long, conflicts_with_all = ["image_width", "image_height"])]
+    pub full_size_images: bool,
+    /// Crops the black bars of letterboxed videos out of screenshots.
+    #[arg(long)]
+    pub crop_letterbox: bool,
+    /// Burns each line into its screenshot.
+    #[arg(long)]
+    pub burn_subtitles: bool,
+    /// The font to burn lines in with, as a family name or a font file.
+    #[arg(long, default_value = screenshot::DEFAULT_SUBTITLE_FONT)]
+    pub subtitle_font: String,
+    /// Also records a video clip of each line: webm, mp4, webp or gif.
+    #[arg(long)]
+    pub video_clips: Option<VideoFormat>,
+    /// The constant rate factor of WebM and MP4 clips, where lower is better.
+    #[arg(long, default_value_t = VideoOptions::default().crf)]
+    pub video_crf: u8,
+    /// The height of video clips.
+    #[arg(long, default_value_t = 360, value_parser = clap::value_parser!(u32).range(1..))]
+    pub video_height: u32,
+    /// The frame rate of video clips, defaulting to the video's for WebM and
+    /// MP4 and to 10 for animations.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub video_fps: Option<u32>,
+    /// Leaves the audio out of WebM and MP4 clips.
+    #[arg(long)]
+    pub silent_video: bool,
+    /// Also writes each video's speech into one condensed audio file.
+    #[arg(long)]
+    pub condensed: bool,
+    /// Seconds of audio kept around each line in the condensed audio.
+    #[arg(long, default_value_t = CondensedOptions::default().padding)]
+    pub condensed_padding: f64,
+    /// Seconds of silence between lines in the condensed audio.
+    #[arg(long, default_value_t = CondensedOptions::default().gap)]
+    pub condensed_gap: f64,
+    /// Splits the condensed audio into files of about this many minutes.
+    #[arg(long, default_value_t = 0.0)]
+    pub chapter_minutes: f64,
+}
+
+/// Runs `command`, printing progress and errors to stderr. Returns a failure
+/// exit code if anything could not be generated.
+pub async fn run(command: Command) -> ExitCode {
+    let result = match command {
+        Command::Generate(args) => generate(args).await,
+        Command::Batch(args) => generate_batch(args).await,
+        Command::Probe { video } => list_tracks(video).await,
+        Command::ListSubs {
+            subtitle,
+            native,
+            timing,
+        } => timing
+            .options(&subtitle)
+            .and_then(|timing| list_subs(&subtitle, native.as_deref(), timing)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn generate(args: GenerateArgs) -> Result<(), String> {
+    let options = &args.output;
+    let deck_name = options
+        .deck
+        .clone()
+        .unwrap_or_else(|| deck_name_for(&args.video));
+    let job = EpisodeJob {
+        video_path: args.video.clone(),
+        naming: MediaNaming {
+            template: parse_template(options)?,
+            deck: deck_name.clone(),
+            video_path: args.video.clone(),
+            episode: None,
+        },
+        clips: load_clips(
+            &args.subtitle,
+            args.native.as_deref(),
+            args.timing.options(&args.subtitle)?,
+        )?
+        .into_iter()
+        .filter(|clip| !options.excluded_styles.contains(&clip.style))
+        .collect(),
+        language: probe::subtitle_language(&args.subtitle),
+    };
+    if job.clips.is_empty() {
+        return Err(format!("{} has no lines to generate", args.subtitle));
+    }
+
+    run_jobs(vec![job], &deck_name, options).await
+}
+
+async fn generate_batch(args: BatchArgs) -> Result<(), String> {
+    let list = |dir: &PathBuf, extensions| {
+        batch::list_files(dir, extensions)
+            .map_err(|error| format!("unable to list {}: {}", dir.display(), error))
+    };
+    let episodes = batch::match_episodes(
+        &list(&args.videos, batch::VIDEO_EXTENSIONS)?,
+        &list(&args.subtitles, batch::SUBTITLE_EXTENSIONS)?,
+    );
+    for episode in &episodes {
+        let number = match episode.number {
+            Some(number) => format!("E{:02}", number),
+            None => "?".to_string(),
+        };
+        let subtitle = match &episode.subtitle {
+            Some(subtitle) => subtitle.display().to_string(),
+            None => "(skipped, no subtitle matched)".to_string(),
+        };
+        eprintln!("{}\t{}\t{}", number, episode.video.display(), subtitle);
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let options = &args.output;
+    let template = parse_template(options)?.with_episode_prefix();
+    let deck_name = options.deck.clone().unwrap_or_else(|| {
+        args.videos
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("subs2srs")
+            .to_string()
+    });
+    let mut jobs = Vec::new();
+    for episode in episodes {
+        let Some(subtitle) = episode.subtitle else {
+            continue;
+        };
+        let subtitle = subtitle.display().to_string();
+        // A subtitle that cannot be read skips its episode, as in the GUI.
+        let clips = TimingOptions::load(&subtitle)
+            .map_err(|error| error.to_string())
+            .and_then(|timing| load_clips(&subtitle, None, timing));
+        let clips = match clips {
+            Ok(clips) => clips,
+            Err(error) => {
+                eprintln!("skipping {}: {}", episode.video.display(), error);
+                continue;
+            }
+        };
+        let video_path = episode.video.display().to_string();
+        jobs.push(EpisodeJob {
+            naming: MediaNaming {
+                template: template.clone(),
+                deck: deck_name.clone(),
+                video_path: video_path.clone(),
+                episode: episode.number,
+            },
+            video_path,
+            clips: clips
+                .into_iter()
+                .filter(|clip| !options.excluded_styles.contains(&clip.style))
+                .collect(),
+            language: probe::subtitle_language(&subtitle),
+        });
+    }
+    if jobs.iter().all(|job| job.clips.is_empty()) {
+        return Err("no episodes have lines to generate".to_string());
+    }
+
+    run_jobs(jobs, &deck_name, options).await
+}
+
+fn parse_template(options: &OutputArgs) -> Result<NamingTemplate, String> {
+    NamingTemplate::parse(&options.name_template).map_err(|error| error.to_string())
+}
+
+// Generates `jobs`, printing each clip as it finishes, and exports the deck.
+async fn run_jobs(
+    mut jobs: Vec<EpisodeJob>,
+    deck_name: &str,
+    options: &OutputArgs,
+) -> Result<(), String> {
+    if options.jobs == 0 {
+        return Err("--jobs must be at least 1".to_string());
+    }
+    // ffmpeg is given the media paths as strings.
+    let output_dir = options
+        .output
+        .to_str()
+        .ok_or_else(|| format!("{} is not a valid UTF-8 path", options.output.display()))?
+        .to_string();
+    if options.sync {
+        sync_jobs(&mut jobs, options).await?;
+    }
+    std::fs::create_dir_all(&options.output)
+        .map_err(|error| format!("unable to create {}: {}", options.output.display(), error))?;
+
+    let total: usize = jobs.iter().map(|job| job.clips.len()).sum();
+    let (tx, rx) = std::sync::mpsc::channel();
+    // Prints each event as it arrives, returning how many condensed audio files failed.
+    let reporter = std::thread::spawn(move || {
+        let mut completed = 0;
+        let mut condensed_failures = 0;
+        for event in rx {
+            match event {
+                ClipEvent::Generated(generated) => {
+                    completed += 1;
+                    eprintln!(
+                        "[{}/{}] {}",
+                        completed,
+                        total,
+                        generated.audio_path.display()
+                    );
+                }
+                ClipEvent::Failed { index, error } => {
+                    completed += 1;
+                    eprintln!("[{}/{}] clip {} failed: {}", completed, total, index, error);
+                }
+                ClipEvent::Condensed {
+                    result: Ok(files), ..
+                } => {
+                    for file in files {
+                        eprintln!("condensed audio {}", file.display());
+                    }
+                }
+                ClipEvent::Condensed {
+                    video_path,
+                    result: Err(error),
+                } => {
+                    condensed_failures += 1;
+                    eprintln!("unable to condense the audio of {}: {}", video_path, error);
+                }
+            }
+        }
+        condensed_failures
+    });
+    let condensed = options.condensed.then_some(CondensedOptions {
+        padding: options.condensed_padding,
+        gap: options.condensed_gap,
+        chapter_minutes: options.chapter_minutes,
+    });
+    let clip_options = ClipOptions {
+        padding: PaddingOptions {
+            lead_in: options.lead_in,
+            lead_out: options.lead_out,
+            avoid_neighbours: !options.allow_overlap,
+            snap_to_silence: options.snap_to_silence,
+        },
+        encoding: AudioEncoding {
+            bitrate: options
+                .audio_bitrate
+                .unwrap_or_else(|| options.audio_codec.default_bitrate()),
+            mp3_vbr_quality: options.mp3_vbr_quality,
+            sample_rate: options.sample_rate,
+            mono: options.mono,
+            ..AudioEncoding::new(options.audio_codec)
+        },
+        image: ImageOptions {
+            format: options.image_format,
+            quality: options.image_quality,
+            size: match (
+                options.full_size_images,
+                options.image_width,
+                options.image_height,
+            ) {
+                (true, _, _) => ImageSize::Original,
+                (false, Some(width), Some(height)) => ImageSize::Fit { width, height },
+                (false, Some(width), None) => ImageSize::Width(width),
+                (false, None, Some(height)) => ImageSize::Height(height),
+                (false, None, None) => ImageSize::default(),
+            },
+            crop_letterbox: options.crop_letterbox,
+            subtitle: options.burn_subtitles.then(|| SubtitleOverlay {
+                font: options.subtitle_font.clone(),
+                ..Default::default()
+            }),
+        },
+        video: options.video_clips.map(|format| VideoOptions {
+            format,
+            crf: options.video_crf,
+            size: ImageSize::Height(options.video_height),
+            frame_rate: options
+                .video_fps
+                .or_else(|| format.is_animation().then_some(10)),
+            audio: !options.silent_video,
+        }),
+        audio_stream: options.audio_stream,
+        lines_per_pass: options
+            .single_pass
+            .map(|lines| lines.unwrap_or(extraction::DEFAULT_LINES_PER_PASS)),
+    };
+    let results =
+        batch::process_episodes(jobs, output_dir, clip_options, options.jobs, condensed, tx).await;
+    let condensed_failures = reporter.join().expect("progress reporter panicked");
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    let generated: Vec<_> = results.into_iter().filter_map(Result::ok).collect();
+    let export_errors = export_deck(deck_name, &generated, &options.output);
+    for error in &export_errors {
+        eprintln!("{}", error);
+    }
+
+    if failed > 0 {
+        Err(format!("{} of {} clips failed", failed, total))
+    } else if !export_errors.is_empty() {
+        Err(format!("unable to export {}", deck_name))
+    } else if condensed_failures > 0 {
+        Err(format!(
+            "{} condensed audio files failed",
+            condensed_failures
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// Shifts the lines of each of `jobs` onto the speech in the audio track
+// that will be recorded.
+async fn sync_jobs(jobs: &mut [EpisodeJob], options: &OutputArgs) -> Result<(), String> {
+    let sync_options = SyncOptions {
+        max_offset: options.max_sync_offset,
+        split_penalty: options.sync_splits,
+    };
+    for job in jobs {
+        if job.clips.is_empty() {
+            continue;
+        }
+        let mut stream = options.audio_stream;
+        if stream.is_none()
+            && let Some(language) = &job.language
+            && let Ok(streams) = probe::audio_streams(job.video_path.clone()).await
+        {
+            stream = probe::preferred_audio_stream(&streams, language);
+        }
+        let alignment = sync::synchronize(&job.video_path, stream, &job.clips, sync_options)
+            .await
+            .map_err(|error| format!("unable to sync {}: {}", job.video_path, error))?;
+        eprintln!(
+            "synced {} by {:+.2}s with {} splits",
+            job.video_path,
+            alignment.offset(),
+            alignment.splits()
+        );
+        job.clips = alignment
+            .apply(std::mem::take(&mut job.clips))
+            .map_err(|error| format!("unable to sync {}: {}", job.video_path, error))?;
+    }
+    Ok(())
+}
+
+async fn list_tracks(video: String) -> Result<(), String> {
+    let info = probe::inspect(video)
+        .await
+        .map_err(|error| error.to_string())?;
+    println!("{} ({})", info.summary(), info.format);
+    for stream in &info.audio {
+        println!("audio {}", stream.label());
+    }
+    for stream in &info.subtitles {
+        if stream.is_text() {
+            println!("subtitle {}", stream.label());
+        } else {
+            println!("subtitle {} [image, cannot be extracted]", stream.label());
+        }
+    }
+    for chapter in &info.chapters {
+        println!(
+            "chapter {}\t{}\t{}",
+            timing::format_time(chapter.start.max(0.0)),
+            timing::format_time(chapter.end.max(0.0)),
+            chapter.title.as_deref().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+fn list_subs(subtitle: &str, native: Option<&str>, timing: TimingOptions) -> Result<(), String> {
+    for clip in load_clips(subtitle, native, timing)? {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            clip.index,
+            timing::format_time(clip.start_time.as_secs_f64()),
+            timing::format_time(clip.end_time.as_secs_f64()),
+            clip.style,
+            clip.text.replace('\n', " "),
+            clip.meaning.replace('\n', " "),
+        );
+    }
+    Ok(())
+}
+
+// Loads the lines of `subtitle` corrected by `timing`, pairing each line with
+// the lines of `native` if given. The native subtitles
+// are corrected by the timing saved for them, since they often come from a
+// different release.
+fn load_clips(
+    subtitle: &str,
+    native: Option<&str>,
+    timing: TimingOptions,
+) -> Result<Vec<SubtitleClip>, String> {
+    let parse = |path: &str, timing: TimingOptions| {
+        subtitles::from_file(path)
+            .map_err(|error| format!("unable to parse {}: {}", path, error))
+            .and_then(|clips| {
+                timing
+                    .apply(clips)
+                    .map_err(|error| format!("unable to correct {}: {}", path, error))
+            })
+    };
+    let mut clips = parse(subtitle, timing)?;
+    if let Some(native) = native {
+        let native_timing = TimingOptions::load(native).map_err(|error| error.to_string())?;
+        pair_subtitles(
+            &mut clips,
+            &parse(native, native_timing)?,
+            PairingOptions::default(),
+        );
+    }
+    Ok(clips)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from([
+            "subs2srs-rs",
+            "generate",
+            "--video",
+            "episode01.mkv",
+            "--subtitle",
+            "episode01.ass",
+            "-o",
+            "out",
+            "-j",
+            "3",
+            "--exclude-style",
+            "Signs",
+            "--exclude-style",
+            "Karaoke",
+        ])
+        .unwrap();
+        let Some(Command::Generate(args)) = cli.command else {
+            panic!("expected generate, got {:?}", cli.command);
+        };
+        assert_eq!(args.output.output, PathBuf::from("out"));
+        assert_eq!(args.output.jobs, 3);
+        assert_eq!(args.output.excluded_styles, ["Signs", "Karaoke"]);
+        assert_eq!(args.native, None);
+        assert_eq!(args.output.name_template, naming::DEFAULT_TEMPLATE);
+
+        let cli = Cli::try_parse_from([
+            "subs2srs-rs",
+            "batch",
+            "--videos",
+            "season1",
+            "--subtitles",
+            "subs",
+            "-o",
+            "out",
+            "--dry-run",
+        ])
+        .unwrap();
+        let Some(Command::Batch(args)) = cli.command else {
+            panic!("expected batch, got {:?}", cli.command);
+        };
+        assert!(args.dry_run);
+        assert_eq!(args.videos, PathBuf::from("season1"));
+
+        assert!(
+            Cli::try_parse_from(["subs2srs-rs"])
+                .unwrap()
+                .command
+                .is_none()
+        );
+        assert!(Cli::try_parse_from(["subs2srs-rs", "generate", "--video", "a.mkv"]).is_err());
+    }
+
+    #[test]
+    fn test_save_timing_needs_a_correction() {
+        let timing = TimingArgs {
+            shift: None,
+            stretch: None,
+            frame_rate: None,
+            save_timing: true,
+        };
+        assert!(timing.options("episode01.srt").is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::SubtitleClip;
+use crate::audio::AudioEncoding;
+use crate::error::Error;
+use crate::ffmpeg::FFmpegBuilder;
+use std::path::PathBuf;
+
+/// CondensedOptions controls how the speech of an episode is joined into one
+/// condensed audio file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CondensedOptions {
+    /// Seconds of audio kept before and after each subtitle line.
+    pub padding: f64,
+    /// Seconds of silence between lines.
+    pub gap: f64,
+    /// Starts a new file after this many minutes of condensed audio, at the
+    /// next line. Zero keeps everything in one file.
+    pub chapter_minutes: f64,
+}
+
+impl Default for CondensedOptions {
+    fn default() -> Self {
+        CondensedOptions {
+            padding: 0.25,
+            gap: 0.5,
+            chapter_minutes: 0.0,
+        }
+    }
+}
+
+/// Region is a stretch of the input that is kept, in seconds. The audio from
+/// `silence` to `end` is muted to separate it from the next region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Region {
+    start: f64,
+    silence: f64,
+    end: f64,
+}
+
+/// Records the audio of every clip into condensed audio at `output`, which
+/// must end in the extension of `encoding`, with a single `ffmpeg` run.
+///
+/// Returns the files written, which are numbered like `output_01.mp3` when
+/// the audio is split into chapters.
+pub async fn record_condensed_audio(
+    clips: &[SubtitleClip],
+    input: String,
+    output: PathBuf,
+    options: CondensedOptions,
+    encoding: AudioEncoding,
+    stream: Option<u32>,
+) -> Result<Vec<PathBuf>, Error> {
+    if options.padding < 0.0 || options.gap < 0.0 || options.chapter_minutes < 0.0 {
+        return Err(Error::InvalidArgument(format!(
+            "condensed audio options must not be negative: {:?}",
+            options
+        )));
+    }
+    let regions = regions(clips, &options);
+    if regions.is_empty() {
+        return Err(Error::InvalidArgument(
+            "there are no lines to condense".to_string(),
+        ));
+    }
+
+    let chapters = chapter_starts(&regions, options.chapter_minutes);
+    let (output_pattern, outputs) = if chapters.is_empty() {
+        (output.clone(), vec![output])
+    } else {
+        let stem = output
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("");
+        let extension = output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("");
+        let numbered =
+            |number: &str| output.with_file_name(format!("{}_{}.{}", stem, number, extension));
+        let outputs = (1..=chapters.len() + 1)
+            .map(|number| numbered(&format!("{:02}", number)))
+            .collect();
+        (numbered("%02d"), outputs)
+    };
+
+    let mut builder = FFmpegBuilder::new(input, output_pattern.to_str().unwrap().to_string())
+        .overwrite()
+        .disable_video()
+        .encode_audio(encoding)
+        .audio_filter(filter(&regions));
+    if !chapters.is_empty() {
+        builder = builder.segment_at(chapters);
+    }
+    if let Some(stream) = stream {
+        builder = builder.map_audio_stream(stream);
+    }
+    builder.build()?.run().await?;
+
+    Ok(outputs)
+}
+
+// Pads each clip and merges the clips whose padding or gap would overlap the
+// next one, so no audio is played twice.
+fn regions(clips: &[SubtitleClip], options: &CondensedOptions) -> Vec<Region> {
+    let mut ranges: Vec<(f64, f64)> = clips
+        .iter()
+        .filter(|clip| clip.end_time > clip.start_time)
+        .map(|clip| {
+            (
+                (clip.start_time.as_secs_f64() - options.padding).max(0.0),
+                clip.end_time.as_secs_f64() + options.padding,
+            )
+        })
+        .collect();
+    ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut regions: Vec<Region> = Vec::new();
+    for (start, end) in ranges {
+        match regions.last_mut() {
+            Some(last) if start <= last.end => {
+                last.silence = last.silence.max(end);
+                last.end = last.silence + options.gap;
+            }
+            _ => regions.push(Region {
+                start,
+                silence: end,
+                end: end + options.gap,
+            }),
+        }
+    }
+    // Nothing follows the last line, so it needs no gap.
+    if let Some(last) = regions.last_mut() {
+        last.end = last.silence;
+    }
+    regions
+}
+
+// The times in the condensed audio at which to start a new chapter, at the
+// first region once the current chapter is `minutes` long.
+fn chapter_starts(regions: &[Region], minutes: f64) -> Vec<f64> {
+    let mut starts = Vec::new();
+    if minutes <= 0.0 {
+        return starts;
+    }
+
+    let mut elapsed = 0.0;
+    let mut chapter_start = 0.0;
+    for region in regions {
+        if elapsed - chapter_start >= minutes * 60.0 {
+            starts.push(elapsed);
+            chapter_start = elapsed;
+        }
+        elapsed += region.end - region.start;
+    }
+    starts
+}
+
+// Mutes the gaps, keeps only the regions, and then closes up the timestamps.
+fn filter(regions: &[Region]) -> String {
+    let ranges = |range: fn(&Region) -> (f64, f64)| {
+        regions
+            .iter()
+            .map(range)
+            .filter(|(start, end)| end > start)
+            .map(|(start, end)| format!("between(t,{:.3},{:.3})", start, end))
+            .collect::<Vec<_>>()
+            .join("+")
+    };
+    let kept = ranges(|region| (region.start, region.end));
+    let silent = ranges(|region| (region.silence, region.end));
+
+    let mut filters = Vec::new();
+    if !silent.is_empty() {
+        filters.push(format!("volume=0:enable='{}'", silent));
+    }
+    filters.push(format!("aselect='{}'", kept));
+    filters.push("asetpts=N/SR/TB".to_string());
+    filters.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn clip(start_ms: u64, end_ms: u64) -> SubtitleClip {
+        SubtitleClip {
+            start_time: Duration::from_millis(start_ms),
+            end_time: Duration::from_millis(end_ms),
+            ..Default::default()
+        }
+    }
+
+    fn region(start: f64, silence: f64, end: f64) -> Region {
+        Region {
+            start,
+            silence,
+            end,
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        let clips = [
+            clip(10_000, 12_000),
+            clip(100, 2_000),
+            clip(2_500, 3_000),
+            clip(5_000, 5_000),
+        ];
+        let options = CondensedOptions {
+            padding: 0.25,
+            gap: 0.5,
+            chapter_minutes: 0.0,
+        };
+        // The first two lines are close enough to be merged, and the empty line is dropped.
+        assert_eq!(
+            regions(&clips, &options),
+            [region(0.0, 3.25, 3.75), region(9.75, 12.25, 12.25)]
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let regions = [region(0.0, 3.25, 3.75), region(9.75, 12.25, 12.25)];
+        assert_eq!(
+            filter(&regions),
+            "volume=0:enable='between(t,3.250,3.750)',\
+             aselect='between(t,0.000,3.750)+between(t,9.750,12.250)',\
+             asetpts=N/SR/TB"
+        );
+    }
+
+    #[test]
+    fn test_chapter_starts() {
+        // Five regions of 40s each, split every minute.
+        let regions: Vec<Region> = (0..5)
+            .map(|i| {
+                let start = i as f64 * 100.0;
+                region(start, start + 39.0, start + 40.0)
+            })
+            .collect();
+        assert_eq!(chapter_starts(&regions, 1.0), [80.0, 160.0]);
+        assert!(chapter_starts(&regions, 0.0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_record_condensed_audio_invalid_options() {
+        let options = CondensedOptions {
+            gap: -1.0,
+            ..Default::default()
+        };
+        let result = record_condensed_audio(
+            &[clip(0, 1000)],
+            "in.mkv".into(),
+            "out.mp3".into(),
+            options,
+            AudioEncoding::default(),
+            None,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+
+        let result = record_condensed_audio(
+            &[],
+            "in.mkv".into(),
+            "out.mp3".into(),
+            CondensedOptions::default(),
+            AudioEncoding::default(),
+            None,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+}
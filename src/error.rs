@@ -0,0 +1,132 @@
+use std::fmt;
+
+/// How many lines from the end of a failed command's stderr are kept.
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Error is returned when running `ffmpeg` or `ffprobe` fails.
+#[derive(Debug)]
+pub enum Error {
+    /// The program could not be found on the `PATH`.
+    MissingBinary(String),
+    /// The program ran but exited unsuccessfully.
+    CommandFailed {
+        program: String,
+        /// The exit code, or `None` if the program was killed by a signal.
+        code: Option<i32>,
+        /// The last few lines the program wrote to stderr.
+        stderr: String,
+    },
+    /// The start time is negative or after the end time.
+    InvalidTimeRange {
+        start: f64,
+        end: f64,
+    },
+    /// The clip starts at or after the end of the input.
+    PastEnd {
+        start: f64,
+        duration: f64,
+    },
+    /// A required argument, such as an input or output path, was missing.
+    InvalidArgument(String),
+    /// The program's output could not be understood.
+    InvalidOutput(String),
+    Io(std::io::Error),
+}
+
+impl Error {
+    /// Creates a `CommandFailed` error, keeping only the end of `stderr`.
+    pub(crate) fn command_failed(program: &str, code: Option<i32>, stderr: &[u8]) -> Self {
+        let stderr = String::from_utf8_lossy(stderr);
+        let lines: Vec<&str> = stderr.trim_end().lines().collect();
+        let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n");
+        Error::CommandFailed {
+            program: program.to_string(),
+            code,
+            stderr: tail,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingBinary(program) => {
+                write!(
+                    f,
+                    "{} was not found, is it installed and on the PATH?",
+                    program
+                )
+            }
+            Error::CommandFailed {
+                program,
+                code: Some(code),
+                stderr,
+            } => write!(f, "{} exited with status {}: {}", program, code, stderr),
+            Error::CommandFailed {
+                program,
+                code: None,
+                stderr,
+            } => write!(f, "{} was terminated: {}", program, stderr),
+            Error::InvalidTimeRange { start, end } => {
+                write!(f, "invalid time range {}s to {}s", start, end)
+            }
+            Error::PastEnd { start, duration } => write!(
+                f,
+                "clip starts at {}s, after the end of the input at {}s",
+                start, duration
+            ),
+            Error::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            Error::InvalidOutput(message) => write!(f, "invalid output: {}", message),
+            Error::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_failed_keeps_stderr_tail() {
+        let stderr: String = (1..=15).map(|line| format!("line {}\n", line)).collect();
+        let error = Error::command_failed("ffmpeg", Some(1), stderr.as_bytes());
+
+        let Error::CommandFailed { stderr, .. } = &error else {
+            panic!("expected CommandFailed, got {:?}", error);
+        };
+        assert_eq!(stderr.lines().count(), STDERR_TAIL_LINES);
+        assert!(stderr.starts_with("line 6\n"));
+        assert!(stderr.ends_with("line 15"));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Error::command_failed("ffmpeg", Some(1), b"No such file\n").to_string(),
+            "ffmpeg exited with status 1: No such file"
+        );
+        assert_eq!(
+            Error::InvalidTimeRange {
+                start: 2.5,
+                end: 1.0
+            }
+            .to_string(),
+            "invalid time range 2.5s to 1s"
+        );
+    }
+}
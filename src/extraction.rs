@@ -0,0 +1,294 @@
+use crate::audio::{self, AudioRange};
+use crate::error::Error;
+use crate::ffmpeg::{FFmpeg, timestamp};
+use crate::screenshot::{self, Crop};
+use crate::{ClipOptions, GeneratedClip, SubtitleClip, video};
+use std::path::PathBuf;
+
+/// The number of lines extracted per ffmpeg command by default. Larger passes
+/// open the input less often, but decode more of it between lines.
+pub const DEFAULT_LINES_PER_PASS: usize = 20;
+
+/// PlannedClip is a line whose media is extracted in a shared pass over the input.
+#[derive(Debug, Clone)]
+pub(crate) struct PlannedClip {
+    pub clip: SubtitleClip,
+    /// The path of the clip's media files, without their extensions.
+    pub media_stem: PathBuf,
+    /// The span of the input recorded as the clip's audio and video.
+    pub range: AudioRange,
+}
+
+impl PlannedClip {
+    // The screenshot is taken halfway through the line, as `process_clip` does.
+    fn screenshot_time(&self) -> f64 {
+        let clip = &self.clip;
+        (clip.start_time + (clip.end_time - clip.start_time) / 2).as_secs_f64()
+    }
+
+    // The path of the media file with `extension`, named as the per-clip
+    // functions name it.
+    fn media_path(&self, extension: &str) -> String {
+        format!("{}.{}", self.media_stem.display(), extension)
+    }
+}
+
+/// Builds a single ffmpeg command that writes the screenshot, audio and video
+/// clip of every one of `clips`, along with the clips it generates. Without an
+/// `audio_stream` in `options`, the first audio stream is recorded.
+///
+/// The input is read once, from the start of the earliest clip to the end of
+/// the latest, and split into a `trim` or `atrim` branch per file in one
+/// filtergraph. Each file is encoded exactly as the per-clip functions encode
+/// it, so the output is the same as running them one by one. Clips should be
+/// close together, since everything between them is decoded too.
+pub(crate) fn plan_extraction(
+    input: &str,
+    clips: &[PlannedClip],
+    options: &ClipOptions,
+    crop: Option<Crop>,
+) -> Result<(FFmpeg, Vec<GeneratedClip>), Error> {
+    if clips.is_empty() {
+        return Err(Error::InvalidArgument("no clips to extract".to_string()));
+    }
+    let start = clips
+        .iter()
+        .map(|clip| clip.range.start.min(clip.screenshot_time()))
+        .fold(f64::INFINITY, f64::min);
+    let end = clips
+        .iter()
+        .map(|clip| clip.range.end.max(clip.screenshot_time()))
+        .fold(f64::NEG_INFINITY, f64::max);
+    // The input is seeked to `start`, so the filters see it as time 0.
+    let at = |time: f64| timestamp(time - start);
+    let video_audio = options.video.is_some_and(|video| video.has_audio());
+
+    let mut video_branches = Vec::new();
+    let mut audio_branches = Vec::new();
+    let mut outputs = Vec::new();
+    let mut generated = Vec::new();
+    for (i, planned) in clips.iter().enumerate() {
+        let range = planned.range;
+
+        let screenshot_path = planned.media_path(options.image.format.extension());
+        video_branches.push(format!(
+            "trim=start={},trim=end_frame=1,setpts=PTS-STARTPTS[shot{}]",
+            at(planned.screenshot_time()),
+            i
+        ));
+        outputs.push(
+            screenshot::screenshot_builder(
+                input.to_string(),
+                screenshot_path.clone(),
+                &options.image,
+                crop,
+                &planned.clip.text,
+            )
+            .map_video(format!("shot{}", i)),
+        );
+
+        let audio_path = planned.media_path(options.encoding.codec.extension());
+        audio_branches.push(format!(
+            "atrim=start={}:end={},asetpts=PTS-STARTPTS[audio{}]",
+            at(range.start),
+            at(range.end),
+            i
+        ));
+        outputs.push(
+            audio::audio_clip_builder(input.to_string(), audio_path.clone(), options.encoding)
+                .map_audio(format!("audio{}", i)),
+        );
+
+        let video_path = match options.video {
+            Some(video_options) => {
+                let video_path = planned.media_path(video_options.format.extension());
+                video_branches.push(format!(
+                    "trim=start={}:end={},setpts=PTS-STARTPTS[video{}]",
+                    at(range.start),
+                    at(range.end),
+                    i
+                ));
+                let mut output = video::video_clip_builder(
+                    input.to_string(),
+                    video_path.clone(),
+                    video_options,
+                    crop,
+                )
+                .map_video(format!("video{}", i));
+                if video_audio {
+                    audio_branches.push(format!(
+                        "atrim=start={}:end={},asetpts=PTS-STARTPTS[videoaudio{}]",
+                        at(range.start),
+                        at(range.end),
+                        i
+                    ));
+                    output = output.map_audio(format!("videoaudio{}", i));
+                }
+                outputs.push(output);
+                Some(PathBuf::from(video_path))
+            }
+            None => None,
+        };
+
+        generated.push(GeneratedClip {
+            clip: planned.clip.clone(),
+            screenshot_path: PathBuf::from(screenshot_path),
+            audio_path: PathBuf::from(audio_path),
+            video_path,
+        });
+    }
+
+    let graph = [
+        split_graph("0:v:0", "split", "v", &video_branches),
+        split_graph(
+            &format!("0:a:{}", options.audio_stream.unwrap_or(0)),
+            "asplit",
+            "a",
+            &audio_branches,
+        ),
+    ]
+    .join(";");
+    let mut outputs = outputs.into_iter();
+    let mut command = outputs
+        .next()
+        .expect("every clip has a screenshot")
+        .seek_to(start)
+        .end_at(end)
+        .filter_complex(graph);
+    for output in outputs {
+        command = command.add_output(output);
+    }
+    Ok((command.build()?, generated))
+}
+
+// Splits the input stream `stream` with `filter` into one labelled copy per
+// branch, and feeds each copy into its branch.
+fn split_graph(stream: &str, filter: &str, prefix: &str, branches: &[String]) -> String {
+    let labels: String = (0..branches.len())
+        .map(|i| format!("[{}{}]", prefix, i))
+        .collect();
+    let mut chains = vec![format!(
+        "[{}]{}={}{}",
+        stream,
+        filter,
+        branches.len(),
+        labels
+    )];
+    chains.extend(
+        branches
+            .iter()
+            .enumerate()
+            .map(|(i, branch)| format!("[{}{}]{}", prefix, i, branch)),
+    );
+    chains.join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::video::{VideoFormat, VideoOptions};
+    use std::time::Duration;
+
+    fn planned(index: usize, start_ms: u64, end_ms: u64) -> PlannedClip {
+        PlannedClip {
+            clip: SubtitleClip {
+                index,
+                start_time: Duration::from_millis(start_ms),
+                end_time: Duration::from_millis(end_ms),
+                ..Default::default()
+            },
+            media_stem: PathBuf::from(format!("/out/{}", index)),
+            range: AudioRange {
+                start: start_ms as f64 / 1000.0 - 0.25,
+                end: end_ms as f64 / 1000.0 + 0.25,
+            },
+        }
+    }
+
+    #[test]
+    fn test_plan_extraction() {
+        let clips = [planned(1, 10_000, 12_000), planned(2, 14_000, 15_000)];
+        let (command, generated) =
+            plan_extraction("in.mkv", &clips, &ClipOptions::default(), None).unwrap();
+
+        assert_eq!(generated[1].screenshot_path, PathBuf::from("/out/2.png"));
+        assert_eq!(generated[1].audio_path, PathBuf::from("/out/2.mp3"));
+        assert_eq!(generated[1].video_path, None);
+        let args = command.args();
+        assert_eq!(
+            args[..8],
+            [
+                "-ss",
+                "9.750",
+                "-t",
+                "5.500",
+                "-i",
+                "in.mkv",
+                "-filter_complex",
+                &args[7]
+            ]
+        );
+        assert_eq!(
+            args[7],
+            [
+                "[0:v:0]split=2[v0][v1]",
+                "[v0]trim=start=1.250,trim=end_frame=1,setpts=PTS-STARTPTS[shot0]",
+                "[v1]trim=start=4.750,trim=end_frame=1,setpts=PTS-STARTPTS[shot1]",
+                "[0:a:0]asplit=2[a0][a1]",
+                "[a0]atrim=start=0.000:end=2.500,asetpts=PTS-STARTPTS[audio0]",
+                "[a1]atrim=start=4.000:end=5.500,asetpts=PTS-STARTPTS[audio1]",
+                "[shot0]scale=-1:320[shot0_out]",
+                "[shot1]scale=-1:320[shot1_out]",
+            ]
+            .join(";")
+        );
+        // Every file is written with the flags of its per-clip function.
+        let outputs: Vec<String> = args[8..]
+            .split_inclusive(|arg| arg.starts_with("/out/"))
+            .map(|output| output.join(" "))
+            .collect();
+        assert_eq!(
+            outputs,
+            [
+                "-y -vframes 1 -map [shot0_out] -an /out/1.png",
+                "-y -vn -c:a libmp3lame -b:a 192k -map [audio0] /out/1.mp3",
+                "-y -vframes 1 -map [shot1_out] -an /out/2.png",
+                "-y -vn -c:a libmp3lame -b:a 192k -map [audio1] /out/2.mp3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_extraction_with_video() {
+        let options = ClipOptions {
+            video: Some(VideoOptions {
+                format: VideoFormat::Gif,
+                frame_rate: Some(10),
+                ..Default::default()
+            }),
+            audio_stream: Some(1),
+            ..Default::default()
+        };
+        let clips = [planned(1, 10_000, 12_000), planned(2, 14_000, 15_000)];
+        let (command, generated) = plan_extraction("in.mkv", &clips, &options, None).unwrap();
+
+        assert_eq!(generated[0].video_path, Some(PathBuf::from("/out/1.gif")));
+        let args = command.args();
+        let graph = &args[7];
+        assert!(graph.contains("[0:a:1]asplit=2[a0][a1]"));
+        assert!(graph.contains("[v1]trim=start=0.000:end=2.500,setpts=PTS-STARTPTS[video0]"));
+        // Each GIF gets its own palette.
+        assert!(graph.contains("[video0_palette]"));
+        assert!(graph.contains("[video1_palette]"));
+        assert!(!graph.contains("videoaudio"), "animations are silent");
+        assert!(args.iter().any(|arg| arg == "[video1_out]"));
+    }
+
+    #[test]
+    fn test_plan_extraction_without_clips() {
+        assert!(matches!(
+            plan_extraction("in.mkv", &[], &ClipOptions::default(), None),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
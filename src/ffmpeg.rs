@@ -1,18 +1,129 @@
+use crate::audio::{AudioCodec, AudioEncoding};
+use crate::error::Error;
+use crate::screenshot::{Crop, ImageFormat, ImageSize};
+use crate::video::VideoFormat;
+use tokio::process::Command;
+
+/// The sample rates the Opus encoder accepts.
+const OPUS_SAMPLE_RATES: &[u32] = &[8000, 12000, 16000, 24000, 48000];
+
 /// FFmpeg runs an `ffmpeg` CLI command.
 pub struct FFmpeg {
     input_path: String,
     output_path: String,
+    // Flags that apply to the input, and so come before `-i`.
+    input_flags: Vec<String>,
+    // The chains of the `-filter_complex` filtergraph.
+    filter_graph: Vec<String>,
     flags: Vec<String>,
+    // The flags and paths of the outputs after the first.
+    outputs: Vec<(Vec<String>, String)>,
 }
 
 impl FFmpeg {
     /// Create the arguments for an ffmpeg CLI command.
     pub fn args(self) -> Vec<String> {
-        let mut args = vec!["-i".to_string(), self.input_path];
+        let mut args = self.input_flags;
+        args.extend(["-i".to_string(), self.input_path]);
+        if !self.filter_graph.is_empty() {
+            args.extend(["-filter_complex".to_string(), self.filter_graph.join(";")]);
+        }
         args.extend(self.flags);
         args.push(self.output_path);
+        for (flags, path) in self.outputs {
+            args.extend(flags);
+            args.push(path);
+        }
         args
     }
+
+    /// Runs the command, failing if ffmpeg is missing or exits unsuccessfully.
+    pub async fn run(self) -> Result<(), Error> {
+        execute("ffmpeg", self.args()).await?;
+        Ok(())
+    }
+
+    /// Runs the command like `run`, returning what was written to stdout, as
+    /// with an output path of `-`.
+    pub async fn run_for_stdout(self) -> Result<Vec<u8>, Error> {
+        execute("ffmpeg", self.args()).await
+    }
+
+    /// Runs the command like `run`, returning what ffmpeg printed to stderr,
+    /// which is where analysis filters like `cropdetect` report.
+    pub async fn run_for_stderr(self) -> Result<Vec<u8>, Error> {
+        execute_for_stderr("ffmpeg", self.args()).await
+    }
+}
+
+/// Runs `program` with `args` and returns its stdout.
+///
+/// Stdin is closed so the program can never block on a prompt, and stderr is
+/// captured so that it can be reported if the program fails.
+pub(crate) async fn execute<I, S>(program: &str, args: I) -> Result<Vec<u8>, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    Ok(output(program, args).await?.stdout)
+}
+
+/// Runs `program` with `args` like `execute`, but returns its stderr, which is
+/// where `ffmpeg` prints the results of analysis filters like `silencedetect`.
+pub(crate) async fn execute_for_stderr<I, S>(program: &str, args: I) -> Result<Vec<u8>, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    Ok(output(program, args).await?.stderr)
+}
+
+async fn output<I, S>(program: &str, args: I) -> Result<std::process::Output, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let output = Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        // Cancelled clips drop this future, which should stop the program too.
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => Error::MissingBinary(program.to_string()),
+            _ => Error::Io(error),
+        })?;
+
+    if !output.status.success() {
+        return Err(Error::command_failed(
+            program,
+            output.status.code(),
+            &output.stderr,
+        ));
+    }
+    Ok(output)
+}
+
+/// SeekMode is how `FFmpegBuilder::seek_to` finds the start of the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeekMode {
+    /// Jumps to the keyframe before the time and decodes from there, dropping
+    /// the frames before the time. Fast, and exact to the frame.
+    #[default]
+    Accurate,
+    /// Starts at the keyframe before the time without decoding up to it. The
+    /// fastest, but video may start up to a few seconds early.
+    Keyframe,
+    /// Decodes the whole input up to the time. Exact with any input, but slow
+    /// near the end of long videos.
+    Decode,
+}
+
+/// Formats `seconds` for ffmpeg with millisecond precision, so that clips
+/// start exactly where the subtitle does and the arguments stay readable.
+pub(crate) fn timestamp(seconds: f64) -> String {
+    format!("{:.3}", seconds)
 }
 
 /// FFmpegBuilder builds an FFmpeg struct.
@@ -22,12 +133,31 @@ pub struct FFmpegBuilder {
     output_path: String,
     // Optional arguments
     seek_time: Option<f64>,
+    seek_mode: SeekMode,
     vframes: Option<i32>,
-    scale_height: Option<i32>,
+    scale: Option<ImageSize>,
+    crop: Option<Crop>,
+    image_encoding: Option<(ImageFormat, u8)>,
+    frame_rate: Option<u32>,
+    video_encoding: Option<(VideoFormat, u8)>,
+    video_filters: Vec<String>,
     disable_audio: bool,
     end_time: Option<f64>,
     disable_video: bool,
-    encode_mp3_audio: bool,
+    audio_encoding: Option<AudioEncoding>,
+    map_subtitle_stream: Option<u32>,
+    map_audio_stream: Option<u32>,
+    maps: Vec<String>,
+    mapped_video: Option<String>,
+    mapped_audio: Option<String>,
+    filter_graph: Vec<String>,
+    outputs: Vec<FFmpegBuilder>,
+    overwrite: bool,
+    audio_filter: Option<String>,
+    segment_times: Option<Vec<f64>>,
+    quiet: bool,
+    output_format: Option<String>,
+    pcm_sample_rate: Option<u32>,
 }
 
 impl FFmpegBuilder {
@@ -36,22 +166,50 @@ impl FFmpegBuilder {
             input_path: input_path,
             output_path: output_path,
             seek_time: None,
+            seek_mode: SeekMode::default(),
             vframes: None,
-            scale_height: None,
+            scale: None,
+            crop: None,
+            image_encoding: None,
+            frame_rate: None,
+            video_encoding: None,
+            video_filters: Vec::new(),
             disable_audio: false,
             end_time: None,
             disable_video: false,
-            encode_mp3_audio: false,
+            audio_encoding: None,
+            map_subtitle_stream: None,
+            map_audio_stream: None,
+            maps: Vec::new(),
+            mapped_video: None,
+            mapped_audio: None,
+            filter_graph: Vec::new(),
+            outputs: Vec::new(),
+            overwrite: false,
+            audio_filter: None,
+            segment_times: None,
+            quiet: false,
+            output_format: None,
+            pcm_sample_rate: None,
         }
     }
 
-    // Starts the input at `time`. Equivalent to the FFmpeg `-ss` flag.
+    // Starts the input at `time`. Equivalent to the FFmpeg `-ss` flag, which is
+    // placed before or after `-i` depending on the seek mode.
     pub fn seek_to(mut self, time: f64) -> Self {
         assert!(self.seek_time == None);
         self.seek_time = Some(time);
         self
     }
 
+    // Sets how `seek_to` finds its time. Equivalent to placing `-ss` before `-i`,
+    // with `-noaccurate_seek` for `SeekMode::Keyframe`, or after it for
+    // `SeekMode::Decode`.
+    pub fn seek_mode(mut self, mode: SeekMode) -> Self {
+        self.seek_mode = mode;
+        self
+    }
+
     // Sets the number of frames to output. Equivalent to the FFmpeg `-vframes` flag.
     pub fn output_frames_count(mut self, count: i32) -> Self {
         assert!(self.vframes == None);
@@ -60,9 +218,59 @@ impl FFmpegBuilder {
     }
 
     // Sets the height of the output frames. Equivalent to the FFmpeg `-vf scale=-1:<height>` flag.
-    pub fn scale(mut self, height: i32) -> Self {
-        assert!(self.scale_height == None);
-        self.scale_height = Some(height);
+    pub fn scale(self, height: u32) -> Self {
+        self.scale_to(ImageSize::Height(height))
+    }
+
+    // Scales the output frames to `size`, keeping the aspect ratio. Equivalent to
+    // the FFmpeg `-vf scale=<width>:<height>` flag.
+    pub fn scale_to(mut self, size: ImageSize) -> Self {
+        assert!(self.scale.is_none());
+        self.scale = Some(size);
+        self
+    }
+
+    // Crops the output frames to `crop`, before any scaling. Equivalent to the
+    // FFmpeg `-vf crop=<width>:<height>:<x>:<y>` flag.
+    pub fn crop(mut self, crop: Crop) -> Self {
+        assert!(self.crop.is_none());
+        self.crop = Some(crop);
+        self
+    }
+
+    // Encodes the output frames as `format` at `quality`, from 1 to 100. The
+    // format must match the output extension. Equivalent to the FFmpeg `-q:v`
+    // flag for JPEG, `-c:v libwebp -quality` for WebP and `-c:v libaom-av1 -crf`
+    // for AVIF.
+    pub fn encode_image(mut self, format: ImageFormat, quality: u8) -> Self {
+        assert!(self.image_encoding.is_none());
+        self.image_encoding = Some((format, quality));
+        self
+    }
+
+    // Drops or duplicates frames to output `fps` frames per second. Equivalent
+    // to the FFmpeg `-vf fps=<fps>` flag.
+    pub fn frame_rate(mut self, fps: u32) -> Self {
+        assert!(self.frame_rate.is_none());
+        self.frame_rate = Some(fps);
+        self
+    }
+
+    // Encodes the output video as `format`, with the constant rate factor `crf`
+    // for WebM and MP4. The format must match the output extension. Equivalent
+    // to the FFmpeg `-c:v libvpx-vp9 -crf <crf> -b:v 0` flags for WebM and
+    // `-c:v libx264 -crf <crf>` for MP4. GIFs get their own palette.
+    pub fn encode_video(mut self, format: VideoFormat, crf: u8) -> Self {
+        assert!(self.video_encoding.is_none());
+        self.video_encoding = Some((format, crf));
+        self
+    }
+
+    // Appends `filter` to the video filtergraph, after any crop, frame rate and
+    // scale. Values in the filter must be escaped with `escape_filter_value`.
+    // Equivalent to the FFmpeg `-vf <filter>` flag.
+    pub fn video_filter(mut self, filter: String) -> Self {
+        self.video_filters.push(filter);
         self
     }
 
@@ -73,7 +281,8 @@ impl FFmpegBuilder {
         self
     }
 
-    // Ends the output at `time`. Equivalent to the FFmpeg `-to` flag.
+    // Ends the output at `time` in the input. Equivalent to the FFmpeg `-to` flag,
+    // or to `-t` with the length from the seek time when seeking the input.
     pub fn end_at(mut self, time: f64) -> Self {
         assert!(self.end_time == None);
         self.end_time = Some(time);
@@ -88,61 +297,493 @@ impl FFmpegBuilder {
     }
 
     // Encodes the output audio as MP3. Equivalent to the FFmpeg `-c:a libmp3lame -b:a 192k` flag.
-    pub fn encode_mp3_audio(mut self) -> Self {
-        assert!(self.encode_mp3_audio == false);
-        self.encode_mp3_audio = true;
+    pub fn encode_mp3_audio(self) -> Self {
+        self.encode_audio(AudioEncoding::default())
+    }
+
+    // Encodes the output audio with `encoding`. Equivalent to the FFmpeg
+    // `-c:a <encoder> -b:a <bitrate>k` (or `-q:a <quality>`) `-ar <rate> -ac 1` flags.
+    pub fn encode_audio(mut self, encoding: AudioEncoding) -> Self {
+        assert!(self.audio_encoding.is_none());
+        self.audio_encoding = Some(encoding);
         self
     }
 
-    pub fn build(self) -> FFmpeg {
-        assert!(!self.input_path.is_empty());
-        assert!(!self.output_path.is_empty());
-        assert!(
-            !matches!((self.seek_time, self.end_time), (Some(start), Some(end)) if start > end),
-            "seek_time must be less than or equal to end_time if both are Some"
-        );
+    // Selects the `index`th subtitle stream of the input as the only output stream.
+    // Equivalent to the FFmpeg `-map 0:s:<index>` flag.
+    pub fn map_subtitle_stream(mut self, index: u32) -> Self {
+        assert!(self.map_subtitle_stream.is_none());
+        self.map_subtitle_stream = Some(index);
+        self
+    }
+
+    // Selects the `index`th audio stream of the input, along with the first video
+    // stream unless video is disabled. Equivalent to the FFmpeg
+    // `-map 0:v:0? -map 0:a:<index>` flags.
+    pub fn map_audio_stream(mut self, index: u32) -> Self {
+        assert!(self.map_audio_stream.is_none());
+        self.map_audio_stream = Some(index);
+        self
+    }
+
+    // Selects `stream` for the output, either an input stream like `0:a:1` or a
+    // filtergraph output like `[clip]`. Equivalent to the FFmpeg `-map <stream>` flag.
+    pub fn map(mut self, stream: String) -> Self {
+        self.maps.push(stream);
+        self
+    }
+
+    // Selects the `label` output of the filtergraph as the output video. The
+    // crop, frame rate, scale and video filters are then applied inside the
+    // filtergraph, since filtered streams cannot be filtered again with `-vf`.
+    pub fn map_video(mut self, label: String) -> Self {
+        assert!(self.mapped_video.is_none());
+        self.mapped_video = Some(label);
+        self
+    }
+
+    // Selects the `label` output of the filtergraph as the output audio, applying
+    // any audio filter inside the filtergraph like `map_video`.
+    pub fn map_audio(mut self, label: String) -> Self {
+        assert!(self.mapped_audio.is_none());
+        self.mapped_audio = Some(label);
+        self
+    }
+
+    // Appends `graph`, one or more `;` separated chains, to the filtergraph
+    // shared by every output. Equivalent to the FFmpeg `-filter_complex <graph>` flag.
+    pub fn filter_complex(mut self, graph: String) -> Self {
+        self.filter_graph.push(graph);
+        self
+    }
+
+    // Writes another output from the same input, with the output options of
+    // `output`. Its input path is ignored and it may not seek, since the input
+    // is only read once. With several outputs, `end_at` applies to the input.
+    pub fn add_output(mut self, output: FFmpegBuilder) -> Self {
+        self.outputs.push(output);
+        self
+    }
 
+    // Overwrites the output file if it already exists. Equivalent to the FFmpeg `-y` flag.
+    pub fn overwrite(mut self) -> Self {
+        assert!(!self.overwrite);
+        self.overwrite = true;
+        self
+    }
+
+    // Filters the output audio through a filtergraph. Equivalent to the FFmpeg `-af <filter>` flag.
+    pub fn audio_filter(mut self, filter: String) -> Self {
+        assert!(self.audio_filter.is_none());
+        self.audio_filter = Some(filter);
+        self
+    }
+
+    // Splits the output into numbered files starting at each of `times`, in
+    // seconds of output. The output path must contain a pattern like `%02d`.
+    // Equivalent to the FFmpeg `-f segment -segment_times <times>` flags.
+    pub fn segment_at(mut self, times: Vec<f64>) -> Self {
+        assert!(self.segment_times.is_none());
+        self.segment_times = Some(times);
+        self
+    }
+
+    // Hides the banner and the progress, so that stderr only holds what the
+    // filters print. Equivalent to the FFmpeg `-hide_banner -nostats` flags.
+    pub fn quiet(mut self) -> Self {
+        assert!(!self.quiet);
+        self.quiet = true;
+        self
+    }
+
+    // Discards the output, for commands run only for what their filters print.
+    // The output path should be `-`. Equivalent to the FFmpeg `-f null` flag.
+    pub fn null_output(mut self) -> Self {
+        assert!(self.output_format.is_none());
+        self.output_format = Some("null".to_string());
+        self
+    }
+
+    // Writes the audio as raw mono 16-bit little-endian samples at
+    // `sample_rate` Hz, e.g. to stdout with an output path of `-`. Equivalent
+    // to the FFmpeg `-ac 1 -ar <sample_rate> -f s16le` flags.
+    pub fn decode_to_pcm(mut self, sample_rate: u32) -> Self {
+        assert!(self.output_format.is_none());
+        self.pcm_sample_rate = Some(sample_rate);
+        self.output_format = Some("s16le".to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<FFmpeg, Error> {
+        if self.input_path.is_empty() {
+            return Err(Error::InvalidArgument("input path is empty".to_string()));
+        }
+        if self.output_path.is_empty() {
+            return Err(Error::InvalidArgument("output path is empty".to_string()));
+        }
+        let start = self.seek_time.unwrap_or(0.0);
+        let end = self.end_time.unwrap_or(f64::INFINITY);
+        if start < 0.0 || start > end {
+            return Err(Error::InvalidTimeRange { start, end });
+        }
+
+        let mut input_flags: Vec<String> = Vec::new();
         let mut flags: Vec<String> = Vec::new();
+        let mut filter_graph = self.filter_graph;
 
+        if self.quiet {
+            input_flags.extend(["-hide_banner".to_string(), "-nostats".to_string()]);
+        }
+        if self.overwrite {
+            flags.push("-y".to_string());
+        }
+        if let Some(index) = self.map_subtitle_stream {
+            flags.extend(["-map".to_string(), format!("0:s:{}", index)]);
+        }
+        if let Some(index) = self.map_audio_stream {
+            // Mapping any stream drops the rest, so the video has to be kept explicitly.
+            if !self.disable_video {
+                flags.extend(["-map".to_string(), "0:v:0?".to_string()]);
+            }
+            flags.extend(["-map".to_string(), format!("0:a:{}", index)]);
+        }
+        for stream in self.maps {
+            flags.extend(["-map".to_string(), stream]);
+        }
+        // Seeking the input resets the output timestamps to 0, so the end
+        // becomes a length.
+        let seek_input = self.seek_time.is_some() && self.seek_mode != SeekMode::Decode;
         if let Some(seek_time) = self.seek_time {
-            flags.extend(["-ss".to_string(), seek_time.to_string()]);
+            if self.seek_mode == SeekMode::Keyframe {
+                input_flags.push("-noaccurate_seek".to_string());
+            }
+            let seek = ["-ss".to_string(), timestamp(seek_time)];
+            if seek_input {
+                input_flags.extend(seek);
+            } else {
+                flags.extend(seek);
+            }
         }
         if let Some(vframes) = self.vframes {
             flags.extend(["-vframes".to_string(), vframes.to_string()]);
         }
-        if let Some(scale_height) = self.scale_height {
-            flags.extend(["-vf".to_string(), format!("scale=-1:{}", scale_height)]);
+        let mut video_filters = Vec::new();
+        if let Some(crop) = self.crop {
+            video_filters.push(format!(
+                "crop={}:{}:{}:{}",
+                crop.width, crop.height, crop.x, crop.y
+            ));
+        }
+        if let Some(fps) = self.frame_rate {
+            if fps == 0 {
+                return Err(Error::InvalidArgument("frame rate is 0".to_string()));
+            }
+            video_filters.push(format!("fps={}", fps));
+        }
+        // H.264 and VP9 need even dimensions.
+        let even = matches!(
+            self.video_encoding,
+            Some((VideoFormat::Webm | VideoFormat::Mp4, _))
+        );
+        if let Some(size) = self.scale {
+            video_filters.extend(scale_filter(size, even)?);
+        }
+        video_filters.extend(self.video_filters);
+        if let Some((VideoFormat::Gif, _)) = self.video_encoding {
+            // A palette made from the clip itself looks far better than the default
+            // one. Its labels must be unique within a shared filtergraph.
+            let prefix = match &self.mapped_video {
+                Some(label) => format!("{}_", label),
+                None => String::new(),
+            };
+            video_filters.push(format!(
+                "split[{0}frames][{0}copy];[{0}copy]palettegen[{0}palette];\
+                 [{0}frames][{0}palette]paletteuse",
+                prefix
+            ));
+        }
+        match self.mapped_video {
+            Some(label) => {
+                flags.extend([
+                    "-map".to_string(),
+                    filter_output(&mut filter_graph, label, &video_filters),
+                ]);
+            }
+            None if !video_filters.is_empty() => {
+                flags.extend(["-vf".to_string(), video_filters.join(",")]);
+            }
+            None => {}
+        }
+        if let Some((format, quality)) = self.image_encoding {
+            flags.extend(image_encoding_flags(format, quality)?);
+        }
+        if let Some((format, crf)) = self.video_encoding {
+            flags.extend(video_encoding_flags(format, crf)?);
         }
         if self.disable_audio {
             flags.push("-an".to_string());
         }
         if let Some(end_time) = self.end_time {
-            flags.extend(["-to".to_string(), end_time.to_string()]);
+            // Output options only apply to the first output, so with several
+            // outputs the input is cut short instead.
+            if seek_input && !self.outputs.is_empty() {
+                input_flags.extend(["-t".to_string(), timestamp(end_time - start)]);
+            } else if seek_input {
+                flags.extend(["-t".to_string(), timestamp(end_time - start)]);
+            } else {
+                flags.extend(["-to".to_string(), timestamp(end_time)]);
+            }
         }
         if self.disable_video {
             flags.push("-vn".to_string());
         }
-        if self.encode_mp3_audio {
+        if let Some(encoding) = self.audio_encoding {
+            flags.extend(audio_encoding_flags(&encoding)?);
+        }
+        match (self.mapped_audio, self.audio_filter) {
+            (Some(label), filter) => {
+                let filters: Vec<String> = filter.into_iter().collect();
+                flags.extend([
+                    "-map".to_string(),
+                    filter_output(&mut filter_graph, label, &filters),
+                ]);
+            }
+            (None, Some(filter)) => flags.extend(["-af".to_string(), filter]),
+            (None, None) => {}
+        }
+        if let Some(sample_rate) = self.pcm_sample_rate {
+            if sample_rate == 0 {
+                return Err(Error::InvalidArgument("sample rate is 0".to_string()));
+            }
+            flags.extend([
+                "-ac".to_string(),
+                "1".to_string(),
+                "-ar".to_string(),
+                sample_rate.to_string(),
+            ]);
+        }
+        if let Some(format) = self.output_format {
+            if self.segment_times.is_some() {
+                return Err(Error::InvalidArgument(format!(
+                    "segmented output cannot also be {}",
+                    format
+                )));
+            }
+            flags.extend(["-f".to_string(), format]);
+        }
+        if let Some(times) = self.segment_times {
+            if !self.output_path.contains('%') {
+                return Err(Error::InvalidArgument(format!(
+                    "segmented output {:?} has no number pattern",
+                    self.output_path
+                )));
+            }
+            let times: Vec<String> = times.iter().map(|&time| timestamp(time)).collect();
             flags.extend([
-                "-c:a".to_string(),
-                "libmp3lame".to_string(),
-                "-b:a".to_string(),
-                "192k".to_string(),
+                "-f".to_string(),
+                "segment".to_string(),
+                "-segment_times".to_string(),
+                times.join(","),
+                "-segment_start_number".to_string(),
+                "1".to_string(),
+                "-reset_timestamps".to_string(),
+                "1".to_string(),
             ]);
         }
 
-        FFmpeg {
+        let mut outputs = Vec::new();
+        for output in self.outputs {
+            let output = output.build()?;
+            if !output.input_flags.is_empty() {
+                return Err(Error::InvalidArgument(format!(
+                    "output {:?} seeks, but only the first output may",
+                    output.output_path
+                )));
+            }
+            filter_graph.extend(output.filter_graph);
+            outputs.push((output.flags, output.output_path));
+            outputs.extend(output.outputs);
+        }
+
+        Ok(FFmpeg {
             input_path: self.input_path,
             output_path: self.output_path,
+            input_flags,
+            filter_graph,
             flags: flags,
+            outputs,
+        })
+    }
+}
+
+// Passes the filtergraph output `label` through `filters`, appending the chain
+// to `graph`, and returns the stream to map.
+fn filter_output(graph: &mut Vec<String>, label: String, filters: &[String]) -> String {
+    if filters.is_empty() {
+        return format!("[{}]", label);
+    }
+    graph.push(format!("[{0}]{1}[{0}_out]", label, filters.join(",")));
+    format!("[{}_out]", label)
+}
+
+/// Escapes `value` for use as an option value inside a filtergraph, as in
+/// `drawtext=text=<value>`. Values go through two levels of unescaping: once
+/// when the filter's options are parsed and once when the graph is.
+pub(crate) fn escape_filter_value(value: &str) -> String {
+    let escape = |value: &str, special: &[char]| {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
         }
+        escaped
+    };
+    let option = escape(value, &['\\', '\'', ':']);
+    escape(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn scale_filter(size: ImageSize, even: bool) -> Result<Option<String>, Error> {
+    let auto = if even { "-2" } else { "-1" };
+    let invalid = || {
+        Err(Error::InvalidArgument(format!(
+            "invalid image size {:?}",
+            size
+        )))
+    };
+    Ok(Some(match size {
+        ImageSize::Original => return Ok(None),
+        ImageSize::Height(0) | ImageSize::Width(0) => return invalid(),
+        ImageSize::Fit { width, height } if width == 0 || height == 0 => return invalid(),
+        ImageSize::Height(height) => format!("scale={}:{}", auto, height),
+        ImageSize::Width(width) => format!("scale={}:{}", width, auto),
+        ImageSize::Fit { width, height } => format!(
+            "scale='min({},iw)':'min({},ih)':force_original_aspect_ratio=decrease{}",
+            width,
+            height,
+            if even { ":force_divisible_by=2" } else { "" }
+        ),
+    }))
+}
+
+fn image_encoding_flags(format: ImageFormat, quality: u8) -> Result<Vec<String>, Error> {
+    if !(1..=100).contains(&quality) {
+        return Err(Error::InvalidArgument(format!(
+            "image quality {} is not between 1 and 100",
+            quality
+        )));
+    }
+    // How far below the best quality, from 0 to 1.
+    let loss = (100 - quality) as f64 / 99.0;
+    let flags: Vec<String> = match format {
+        ImageFormat::Png => vec![],
+        // The JPEG scale runs from 2 (best) to 31.
+        ImageFormat::Jpeg => vec!["-q:v".into(), (2.0 + loss * 29.0).round().to_string()],
+        ImageFormat::Webp => vec![
+            "-c:v".into(),
+            "libwebp".into(),
+            "-quality".into(),
+            quality.to_string(),
+        ],
+        // The AV1 scale runs from 0 (best) to 63.
+        ImageFormat::Avif => vec![
+            "-c:v".into(),
+            "libaom-av1".into(),
+            "-still-picture".into(),
+            "1".into(),
+            "-crf".into(),
+            (loss * 63.0).round().to_string(),
+        ],
+    };
+    Ok(flags)
+}
+
+fn video_encoding_flags(format: VideoFormat, crf: u8) -> Result<Vec<String>, Error> {
+    if let Some(max_crf) = format.max_crf()
+        && crf > max_crf
+    {
+        return Err(Error::InvalidArgument(format!(
+            "{} CRF {} is not between 0 and {}",
+            format, crf, max_crf
+        )));
+    }
+    let flags: &[&str] = match format {
+        VideoFormat::Webm => &["-c:v", "libvpx-vp9", "-b:v", "0", "-row-mt", "1"],
+        VideoFormat::Mp4 => &[
+            "-c:v",
+            "libx264",
+            "-preset",
+            "veryfast",
+            "-movflags",
+            "+faststart",
+        ],
+        VideoFormat::AnimatedWebp => &["-c:v", "libwebp_anim", "-loop", "0"],
+        VideoFormat::Gif => &["-loop", "0"],
+    };
+    let mut flags: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+    if format.max_crf().is_some() {
+        flags.extend([
+            "-crf".to_string(),
+            crf.to_string(),
+            "-pix_fmt".to_string(),
+            "yuv420p".to_string(),
+        ]);
+    }
+    Ok(flags)
+}
+
+fn audio_encoding_flags(encoding: &AudioEncoding) -> Result<Vec<String>, Error> {
+    let codec = encoding.codec;
+    let mut flags = vec!["-c:a".to_string(), codec.encoder().to_string()];
+    match (codec, encoding.mp3_vbr_quality) {
+        (AudioCodec::Mp3, Some(quality)) => {
+            if quality > 9 {
+                return Err(Error::InvalidArgument(format!(
+                    "MP3 VBR quality {} is not between 0 and 9",
+                    quality
+                )));
+            }
+            flags.extend(["-q:a".to_string(), quality.to_string()]);
+        }
+        _ if codec.is_lossless() => {}
+        _ => {
+            if encoding.bitrate == 0 {
+                return Err(Error::InvalidArgument(format!(
+                    "{} audio needs a bitrate",
+                    codec
+                )));
+            }
+            flags.extend(["-b:a".to_string(), format!("{}k", encoding.bitrate)]);
+        }
+    }
+    if let Some(rate) = encoding.sample_rate {
+        if rate == 0 || (codec == AudioCodec::Opus && !OPUS_SAMPLE_RATES.contains(&rate)) {
+            return Err(Error::InvalidArgument(format!(
+                "{} audio cannot be sampled at {} Hz",
+                codec, rate
+            )));
+        }
+        flags.extend(["-ar".to_string(), rate.to_string()]);
+    }
+    if encoding.mono {
+        flags.extend(["-ac".to_string(), "1".to_string()]);
     }
+    Ok(flags)
 }
 
 #[cfg(test)]
 mod tests {
     use super::FFmpeg;
     use super::FFmpegBuilder;
+    use super::SeekMode;
+    use super::escape_filter_value;
+    use super::execute;
+    use super::execute_for_stderr;
+    use crate::audio::{AudioCodec, AudioEncoding};
+    use crate::error::Error;
+    use crate::screenshot::{Crop, ImageFormat, ImageSize};
+    use crate::video::VideoFormat;
 
     const INPUT: &str = "/directory/input.mp4";
     const OUTPUT: &str = "/directory/output.png";
@@ -172,7 +813,131 @@ mod tests {
     fn test_ffmpeg_builder_scale() {
         let height = 320;
         let builder = FFmpegBuilder::new(INPUT.into(), OUTPUT.into()).scale(height);
-        assert_eq!(builder.scale_height, Some(height));
+        assert_eq!(builder.scale, Some(ImageSize::Height(height)));
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_video() {
+        let ffmpeg = FFmpegBuilder::new(INPUT.into(), "/directory/output.mp4".into())
+            .scale_to(ImageSize::Height(360))
+            .encode_video(VideoFormat::Mp4, 28)
+            .build()
+            .unwrap();
+        assert_eq!(
+            ffmpeg.flags,
+            [
+                "-vf",
+                "scale=-2:360",
+                "-c:v",
+                "libx264",
+                "-preset",
+                "veryfast",
+                "-movflags",
+                "+faststart",
+                "-crf",
+                "28",
+                "-pix_fmt",
+                "yuv420p"
+            ]
+        );
+
+        let ffmpeg = FFmpegBuilder::new(INPUT.into(), "/directory/output.gif".into())
+            .frame_rate(10)
+            .scale_to(ImageSize::Width(320))
+            .encode_video(VideoFormat::Gif, 0)
+            .disable_audio()
+            .build()
+            .unwrap();
+        assert_eq!(
+            ffmpeg.flags,
+            [
+                "-vf",
+                "fps=10,scale=320:-1,split[frames][copy];[copy]palettegen[palette];[frames][palette]paletteuse",
+                "-loop",
+                "0",
+                "-an"
+            ]
+        );
+
+        let result = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .encode_video(VideoFormat::Mp4, 52)
+            .build();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_video_filters() {
+        let ffmpeg = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .video_filter("drawtext=text=a".into())
+            .scale(320)
+            .video_filter("hflip".into())
+            .build()
+            .unwrap();
+        assert_eq!(ffmpeg.flags, ["-vf", "scale=-1:320,drawtext=text=a,hflip"]);
+    }
+
+    #[test]
+    fn test_escape_filter_value() {
+        assert_eq!(escape_filter_value("你好"), "你好");
+        assert_eq!(escape_filter_value("a:b"), "a\\\\:b");
+        assert_eq!(escape_filter_value("it's"), "it\\\\\\'s");
+        assert_eq!(escape_filter_value("a, b"), "a\\, b");
+        assert_eq!(escape_filter_value("C:\\x"), "C\\\\:\\\\\\\\x");
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_image() {
+        let ffmpeg = FFmpegBuilder::new(INPUT.into(), "/directory/output.jpg".into())
+            .crop(Crop {
+                width: 1920,
+                height: 800,
+                x: 0,
+                y: 140,
+            })
+            .scale_to(ImageSize::Fit {
+                width: 640,
+                height: 360,
+            })
+            .encode_image(ImageFormat::Jpeg, 100)
+            .build()
+            .unwrap();
+        assert_eq!(
+            ffmpeg.flags,
+            [
+                "-vf",
+                "crop=1920:800:0:140,scale='min(640,iw)':'min(360,ih)':force_original_aspect_ratio=decrease",
+                "-q:v",
+                "2"
+            ]
+        );
+
+        let ffmpeg = FFmpegBuilder::new(INPUT.into(), "/directory/output.avif".into())
+            .scale_to(ImageSize::Width(480))
+            .encode_image(ImageFormat::Avif, 1)
+            .build()
+            .unwrap();
+        assert_eq!(
+            ffmpeg.flags,
+            [
+                "-vf",
+                "scale=480:-1",
+                "-c:v",
+                "libaom-av1",
+                "-still-picture",
+                "1",
+                "-crf",
+                "63"
+            ]
+        );
+
+        let result = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .encode_image(ImageFormat::Webp, 0)
+            .build();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        let result = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .scale_to(ImageSize::Width(0))
+            .build();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
     }
 
     #[test]
@@ -197,7 +962,165 @@ mod tests {
     #[test]
     fn test_ffmpeg_builder_encode_mp3_audio() {
         let builder = FFmpegBuilder::new(INPUT.into(), OUTPUT.into()).encode_mp3_audio();
-        assert_eq!(builder.encode_mp3_audio, true);
+        assert_eq!(builder.audio_encoding, Some(AudioEncoding::default()));
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_audio_encodings() {
+        let flags = |encoding: AudioEncoding| {
+            FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+                .encode_audio(encoding)
+                .build()
+                .map(|ffmpeg| ffmpeg.flags)
+        };
+
+        let opus = AudioEncoding {
+            mono: true,
+            sample_rate: Some(24000),
+            ..AudioEncoding::new(AudioCodec::Opus)
+        };
+        assert_eq!(
+            flags(opus).unwrap(),
+            ["-c:a", "libopus", "-b:a", "64k", "-ar", "24000", "-ac", "1"]
+        );
+        let vbr = AudioEncoding {
+            mp3_vbr_quality: Some(4),
+            ..AudioEncoding::default()
+        };
+        assert_eq!(flags(vbr).unwrap(), ["-c:a", "libmp3lame", "-q:a", "4"]);
+        assert_eq!(
+            flags(AudioEncoding::new(AudioCodec::Flac)).unwrap(),
+            ["-c:a", "flac"]
+        );
+
+        let invalid = [
+            AudioEncoding {
+                sample_rate: Some(44100),
+                ..opus
+            },
+            AudioEncoding {
+                mp3_vbr_quality: Some(10),
+                ..vbr
+            },
+            AudioEncoding {
+                bitrate: 0,
+                ..AudioEncoding::new(AudioCodec::Aac)
+            },
+        ];
+        for encoding in invalid {
+            assert!(
+                matches!(flags(encoding), Err(Error::InvalidArgument(_))),
+                "{:?} should be rejected",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_map_subtitle_stream() {
+        let builder = FFmpegBuilder::new(INPUT.into(), OUTPUT.into()).map_subtitle_stream(2);
+        assert_eq!(builder.map_subtitle_stream, Some(2));
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_multiple_outputs() {
+        let args = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .seek_to(10.0)
+            .end_at(12.0)
+            .filter_complex("[0:v]split=2[first][second]".to_string())
+            .map_video("first".to_string())
+            .scale(320)
+            .add_output(
+                FFmpegBuilder::new(INPUT.into(), "second.png".into())
+                    .map_video("second".to_string()),
+            )
+            .build()
+            .unwrap()
+            .args();
+        assert_eq!(
+            args,
+            [
+                "-ss",
+                "10.000",
+                "-t",
+                "2.000",
+                "-i",
+                INPUT,
+                "-filter_complex",
+                "[0:v]split=2[first][second];[first]scale=-1:320[first_out]",
+                "-map",
+                "[first_out]",
+                OUTPUT,
+                "-map",
+                "[second]",
+                "second.png",
+            ]
+        );
+
+        let result = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .add_output(FFmpegBuilder::new(INPUT.into(), "second.png".into()).seek_to(1.0))
+            .build();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_map_audio_stream() {
+        let flags = |builder: FFmpegBuilder| builder.map_audio_stream(1).build().unwrap().flags;
+        assert_eq!(
+            flags(FFmpegBuilder::new(INPUT.into(), OUTPUT.into()).disable_video()),
+            ["-map", "0:a:1", "-vn"]
+        );
+        assert_eq!(
+            flags(FFmpegBuilder::new(INPUT.into(), OUTPUT.into())),
+            ["-map", "0:v:0?", "-map", "0:a:1"]
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_overwrite() {
+        let builder = FFmpegBuilder::new(INPUT.into(), OUTPUT.into()).overwrite();
+        assert!(builder.overwrite);
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_subtitle_extraction() {
+        let ffmpeg = FFmpegBuilder::new(INPUT.into(), "/directory/output.srt".into())
+            .overwrite()
+            .map_subtitle_stream(1)
+            .build()
+            .unwrap();
+        assert_eq!(ffmpeg.flags, ["-y", "-map", "0:s:1"]);
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_segmented_audio() {
+        let ffmpeg = FFmpegBuilder::new(INPUT.into(), "/directory/output_%02d.mp3".into())
+            .disable_video()
+            .audio_filter("asetpts=N/SR/TB".into())
+            .segment_at(vec![600.0, 1200.5])
+            .build()
+            .unwrap();
+        assert_eq!(
+            ffmpeg.flags,
+            [
+                "-vn",
+                "-af",
+                "asetpts=N/SR/TB",
+                "-f",
+                "segment",
+                "-segment_times",
+                "600.000,1200.500",
+                "-segment_start_number",
+                "1",
+                "-reset_timestamps",
+                "1"
+            ]
+        );
+
+        let result = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .segment_at(vec![600.0])
+            .build();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
     }
 
     #[test]
@@ -215,22 +1138,21 @@ mod tests {
             .disable_audio()
             .disable_video()
             .encode_mp3_audio();
-        let ffmpeg = builder.build();
+        let ffmpeg = builder.build().unwrap();
 
         assert_eq!(ffmpeg.input_path, INPUT.to_string());
         assert_eq!(ffmpeg.output_path, OUTPUT.to_string());
+        assert_eq!(ffmpeg.input_flags, ["-ss", "123.400"]);
         assert_eq!(
             ffmpeg.flags,
             [
-                "-ss",
-                "123.4",
                 "-vframes",
                 "2",
                 "-vf",
                 "scale=-1:320",
                 "-an",
-                "-to",
-                "567.8",
+                "-t",
+                "444.400",
                 "-vn",
                 "-c:a",
                 "libmp3lame",
@@ -240,16 +1162,184 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ffmpeg_builder_build_seek_modes() {
+        let build = |mode| {
+            let ffmpeg = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+                .seek_to(1.0 / 3.0)
+                .end_at(2.5)
+                .seek_mode(mode)
+                .build()
+                .unwrap();
+            (ffmpeg.input_flags, ffmpeg.flags)
+        };
+
+        assert_eq!(
+            build(SeekMode::Keyframe),
+            (
+                vec!["-noaccurate_seek".to_string(), "-ss".into(), "0.333".into()],
+                vec!["-t".to_string(), "2.167".into()]
+            )
+        );
+        assert_eq!(
+            build(SeekMode::Decode),
+            (
+                vec![],
+                vec![
+                    "-ss".to_string(),
+                    "0.333".into(),
+                    "-to".into(),
+                    "2.500".into()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_invalid_time_range() {
+        let result = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .seek_to(5.0)
+            .end_at(4.0)
+            .build();
+        assert!(matches!(
+            result,
+            Err(Error::InvalidTimeRange {
+                start: 5.0,
+                end: 4.0
+            })
+        ));
+
+        let result = FFmpegBuilder::new(INPUT.into(), OUTPUT.into())
+            .seek_to(-1.0)
+            .build();
+        assert!(matches!(result, Err(Error::InvalidTimeRange { .. })));
+    }
+
+    #[test]
+    fn test_ffmpeg_builder_build_empty_path() {
+        let result = FFmpegBuilder::new("".into(), OUTPUT.into()).build();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn test_execute_missing_binary() {
+        let result = execute("subs2srs-missing-binary", ["-version"]).await;
+        assert!(
+            matches!(result, Err(Error::MissingBinary(program)) if program == "subs2srs-missing-binary")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_non_zero_exit() {
+        let result = execute("sh", ["-c", "echo out; echo oops >&2; exit 3"]).await;
+        match result {
+            Err(Error::CommandFailed {
+                program,
+                code,
+                stderr,
+            }) => {
+                assert_eq!(program, "sh");
+                assert_eq!(code, Some(3));
+                assert_eq!(stderr, "oops");
+            }
+            other => panic!("expected CommandFailed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_returns_stdout() {
+        let stdout = execute("sh", ["-c", "echo out"]).await.unwrap();
+        assert_eq!(stdout, b"out\n");
+    }
+
+    #[tokio::test]
+    async fn test_execute_for_stderr() {
+        let stderr = execute_for_stderr("sh", ["-c", "echo out; echo err >&2"])
+            .await
+            .unwrap();
+        assert_eq!(stderr, b"err\n");
+    }
+
     #[test]
     fn test_ffmpeg_args() {
         let ffmpeg = FFmpeg {
             input_path: "/input/path".to_string(),
             output_path: "/output/path".to_string(),
+            input_flags: vec!["-ss".into(), "1.000".into()],
+            filter_graph: Vec::new(),
             flags: vec!["-a".into(), "-b".into(), "-c".into()],
+            outputs: Vec::new(),
         };
         assert_eq!(
             ffmpeg.args(),
-            ["-i", "/input/path", "-a", "-b", "-c", "/output/path"]
+            [
+                "-ss",
+                "1.000",
+                "-i",
+                "/input/path",
+                "-a",
+                "-b",
+                "-c",
+                "/output/path"
+            ]
+        );
+    }
+
+    // Times a clip near the end of a long video with each seek mode. It needs
+    // ffmpeg and takes a while, so run it with `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn bench_seek_modes() {
+        let dir = std::env::temp_dir().join(format!("subs2srs_tests/{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.mkv").display().to_string();
+        // Ten minutes of video with a keyframe every ten seconds, like an episode.
+        execute(
+            "ffmpeg",
+            [
+                "-f",
+                "lavfi",
+                "-i",
+                "testsrc=size=640x360:rate=24:duration=600",
+                "-f",
+                "lavfi",
+                "-i",
+                "sine=duration=600",
+                "-c:v",
+                "libx264",
+                "-preset",
+                "ultrafast",
+                "-g",
+                "240",
+                "-c:a",
+                "aac",
+                &input,
+            ],
+        )
+        .await
+        .expect("failed to generate the input video");
+
+        let mut timings = Vec::new();
+        for mode in [SeekMode::Decode, SeekMode::Accurate, SeekMode::Keyframe] {
+            let output = dir.join(format!("{:?}.png", mode)).display().to_string();
+            let started = std::time::Instant::now();
+            FFmpegBuilder::new(input.clone(), output)
+                .seek_to(590.0)
+                .seek_mode(mode)
+                .output_frames_count(1)
+                .build()
+                .unwrap()
+                .run()
+                .await
+                .unwrap();
+            let elapsed = started.elapsed();
+            println!("{:?}: {:?} per clip", mode, elapsed);
+            timings.push(elapsed);
+        }
+        assert!(
+            timings[1] * 5 < timings[0],
+            "seeking the input should be several times faster than decoding: {:?}",
+            timings
         );
     }
 }
@@ -1,11 +1,33 @@
+pub mod ankiconnect;
+pub mod apkg;
+mod ass;
 pub mod audio;
+pub mod batch;
+pub mod cli;
+pub mod condensed;
+pub mod error;
+mod extraction;
 pub mod ffmpeg;
 pub mod frame;
+pub mod naming;
+mod note;
+pub mod pairing;
+pub mod probe;
+mod scheduler;
 pub mod screenshot;
+mod silence;
+pub mod subtitle_track;
+mod subtitles;
+pub mod sync;
+pub mod timing;
+pub mod tsv;
+pub mod video;
+mod vtt;
 
 use eframe::egui;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, Sender};
 
 pub fn run() -> eframe::Result {
@@ -22,30 +44,172 @@ pub fn run() -> eframe::Result {
     )
 }
 
-#[derive(Debug, Default, Clone)]
-struct SubtitleClip {
-    index: usize,
-    start_time: std::time::Duration,
-    end_time: std::time::Duration,
+/// SubtitleClip is a single subtitle line, which becomes a single card.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SubtitleClip {
+    pub index: usize,
+    pub start_time: std::time::Duration,
+    pub end_time: std::time::Duration,
+    pub text: String,
+    // The following are only set for ASS/SSA subtitles.
+    pub style: String,
+    pub actor: String,
+    pub layer: i32,
+    // The translation paired from a native language subtitle file, if any.
+    pub meaning: String,
+}
+
+/// ClipOptions controls the media generated for each clip.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClipOptions {
+    pub padding: audio::PaddingOptions,
+    pub encoding: audio::AudioEncoding,
+    pub image: screenshot::ImageOptions,
+    /// Also records a video clip of each line, over the same span as its audio.
+    pub video: Option<video::VideoOptions>,
+    /// The audio stream to record. `None` picks the stream in the subtitle's
+    /// language, or else the input's default stream.
+    pub audio_stream: Option<u32>,
+    /// Extracts this many lines at a time with one ffmpeg command each, which
+    /// reads the input once instead of once per file.
+    pub lines_per_pass: Option<usize>,
+}
+
+/// GeneratedClip is a `SubtitleClip` along with the media files created for it.
+#[derive(Debug, Clone)]
+struct GeneratedClip {
+    clip: SubtitleClip,
+    screenshot_path: PathBuf,
+    audio_path: PathBuf,
+    video_path: Option<PathBuf>,
+}
+
+impl GeneratedClip {
+    /// The media files of the clip, which exports copy into Anki.
+    fn media_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.audio_path, &self.screenshot_path]
+            .into_iter()
+            .chain(&self.video_path)
+    }
+}
+
+/// NativeSubtitles caches the lines of the native subtitle file, corrected by
+/// its saved timing, and the clips last paired with them.
+struct NativeSubtitles {
+    path: String,
+    lines: Result<Vec<SubtitleClip>, String>,
+    pairing: pairing::PairingOptions,
+    /// The clips before they were paired, and after.
+    unpaired: Vec<SubtitleClip>,
+    paired: Vec<SubtitleClip>,
+}
+
+/// Generation tracks clips being generated in the background.
+struct Generation {
+    total: usize,
+    completed: usize,
+    output_dir: String,
+    deck_name: String,
+    events: Receiver<scheduler::ClipEvent>,
+    task: tokio::task::JoinHandle<Vec<Result<GeneratedClip, error::Error>>>,
 }
 
 struct MyApp {
-    tx: Sender<u32>,
-    rx: Receiver<u32>,
     video_path: String,
     subtitle_path: String,
+    native_subtitle_path: String,
+    pairing: pairing::PairingOptions,
+    /// The native subtitles, loaded when `native_subtitle_path` changes.
+    natives: Option<NativeSubtitles>,
+    embedded_streams: Vec<probe::SubtitleStream>,
+    embedded_status: String,
+    /// Lists the subtitle tracks of the video in the background.
+    embedded_streams_task:
+        Option<tokio::task::JoinHandle<Result<Vec<probe::SubtitleStream>, error::Error>>>,
+    /// Extracts a subtitle track in the background, returning the path it was
+    /// written to.
+    embedded_extract_task: Option<tokio::task::JoinHandle<Result<String, error::Error>>>,
+    audio_streams: Vec<probe::AudioStream>,
+    /// What ffprobe found in `video_path`, refreshed when the path changes.
+    media_info: Option<Result<probe::MediaInfo, String>>,
+    media_info_path: String,
+    media_info_task: Option<tokio::task::JoinHandle<Result<probe::MediaInfo, error::Error>>>,
+    /// The timing correction of `subtitle_path`, loaded when the path changes.
+    timing: timing::TimingOptions,
+    timing_path: String,
+    timing_status: String,
+    /// Aligns the lines to the speech in the video in the background.
+    sync_task: Option<tokio::task::JoinHandle<Result<sync::Alignment, error::Error>>>,
+    /// The subtitle indices of the lines being aligned by `sync_task`.
+    sync_lines: Vec<usize>,
+    sync_options: sync::SyncOptions,
+    /// How far each line was shifted past `timing.offset` by the last sync,
+    /// by subtitle index, when it found splits. These are not saved.
+    line_shifts: BTreeMap<usize, f64>,
     clips: Vec<SubtitleClip>,
+    excluded_styles: BTreeSet<String>,
+    generated: Vec<GeneratedClip>,
+    clip_errors: Vec<String>,
+    generation: Option<Generation>,
+    output_dir: String,
+    naming_template: String,
+    batch_video_dir: String,
+    batch_subtitle_dir: String,
+    batch_subtitles: Vec<PathBuf>,
+    batch_episodes: Vec<batch::Episode>,
+    batch_status: String,
+    condensed_audio: bool,
+    condensed: condensed::CondensedOptions,
+    clip_options: ClipOptions,
+    concurrency: usize,
+    anki_note_type: String,
+    anki_status: String,
+    /// Sends the generated clips to Anki in the background.
+    anki_task: Option<tokio::task::JoinHandle<Result<Vec<ankiconnect::NoteOutcome>, String>>>,
 }
 
 impl Default for MyApp {
     fn default() -> Self {
-        let (tx, rx) = std::sync::mpsc::channel();
         Self {
-            tx,
-            rx,
             video_path: String::new(),
             subtitle_path: String::new(),
+            native_subtitle_path: String::new(),
+            pairing: pairing::PairingOptions::default(),
+            natives: None,
+            embedded_streams: Vec::new(),
+            embedded_status: String::new(),
+            embedded_streams_task: None,
+            embedded_extract_task: None,
+            audio_streams: Vec::new(),
+            media_info: None,
+            media_info_path: String::new(),
+            media_info_task: None,
+            timing: timing::TimingOptions::default(),
+            timing_path: String::new(),
+            timing_status: String::new(),
+            sync_task: None,
+            sync_lines: Vec::new(),
+            sync_options: sync::SyncOptions::default(),
+            line_shifts: BTreeMap::new(),
             clips: Vec::new(),
+            excluded_styles: BTreeSet::new(),
+            generated: Vec::new(),
+            clip_errors: Vec::new(),
+            generation: None,
+            output_dir: std::env::temp_dir().join("subs2srs").display().to_string(),
+            naming_template: naming::DEFAULT_TEMPLATE.to_string(),
+            batch_video_dir: String::new(),
+            batch_subtitle_dir: String::new(),
+            batch_subtitles: Vec::new(),
+            batch_episodes: Vec::new(),
+            batch_status: String::new(),
+            condensed_audio: false,
+            condensed: condensed::CondensedOptions::default(),
+            clip_options: ClipOptions::default(),
+            concurrency: scheduler::default_concurrency(),
+            anki_note_type: apkg::MODEL_NAME.to_string(),
+            anki_status: String::new(),
+            anki_task: None,
         }
     }
 }
@@ -56,55 +220,698 @@ fn convert_subs_to_clips(subs: &[srtparse::Item]) -> Vec<SubtitleClip> {
             index: sub.pos,
             start_time: sub.start_time.into_duration(),
             end_time: sub.end_time.into_duration(),
+            text: sub.text.clone(),
+            ..Default::default()
         })
         .collect()
 }
 
-fn process_clip(input_path: String, output_dir: String, clip: SubtitleClip, progress: Sender<u32>) {
+// Generates the media of `clip` as `media_stem` plus each file's extension.
+async fn process_clip(
+    input_path: String,
+    clip: SubtitleClip,
+    media_stem: PathBuf,
+    audio_range: audio::AudioRange,
+    options: ClipOptions,
+    crop: Option<screenshot::Crop>,
+    progress: Sender<u32>,
+) -> Result<GeneratedClip, error::Error> {
     let start_time = clip.start_time.as_secs_f64();
     let end_time = clip.end_time.as_secs_f64();
+    if end_time <= start_time {
+        return Err(error::Error::InvalidTimeRange {
+            start: start_time,
+            end: end_time,
+        });
+    }
     let mid_time = (clip.start_time + (clip.end_time - clip.start_time) / 2).as_secs_f64();
-    let screenshot_path = Path::new(&output_dir).join(format!("screenshot_{}.png", clip.index));
-    let audio_path = Path::new(&output_dir).join(format!("audio_clip_{}.mp3", clip.index));
-
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .expect("failed to create new tokio runtime");
 
-    // XXX: We should check the results of these two calls.
     // TODO: Change these modules to use a PathBuf so we don't need to
     //       use this to_str().unwrap().to_string() garbage.
-    rt.block_on(screenshot::take_screenshot(
-        mid_time,
-        input_path.clone(),
-        screenshot_path.to_str().unwrap().to_string(),
-    ))
-    .expect("failed to create screenshot");
-    rt.block_on(audio::record_audio_clip(
-        start_time,
-        end_time,
-        input_path.clone(),
-        audio_path.to_str().unwrap().to_string(),
-        progress.clone(),
-    ))
-    .expect("failed to create audio clip");
+    let (screenshot_path, audio_path, video_path) = tokio::try_join!(
+        screenshot::take_screenshot(
+            mid_time,
+            input_path.clone(),
+            media_stem.to_str().unwrap().to_string(),
+            &options.image,
+            crop,
+            &clip.text,
+        ),
+        audio::record_audio_clip(
+            audio_range.start,
+            audio_range.end,
+            input_path.clone(),
+            media_stem.to_str().unwrap().to_string(),
+            options.encoding,
+            options.audio_stream,
+            progress.clone(),
+        ),
+        async {
+            match options.video {
+                Some(video_options) => video::record_video_clip(
+                    audio_range.start,
+                    audio_range.end,
+                    input_path.clone(),
+                    media_stem.to_str().unwrap().to_string(),
+                    video_options,
+                    crop,
+                    options.audio_stream,
+                )
+                .await
+                .map(Some),
+                None => Ok(None),
+            }
+        },
+    )?;
+
+    Ok(GeneratedClip {
+        clip,
+        screenshot_path: PathBuf::from(screenshot_path),
+        audio_path: PathBuf::from(audio_path),
+        video_path: video_path.map(PathBuf::from),
+    })
 }
 
 impl MyApp {
-    fn generate_clips(&self) {
-        let output_dir = "/tmp/subs2srs_test";
-        if !Path::new(output_dir).exists() {
-            fs::create_dir_all(output_dir).expect("Failed to create output directory");
+    // Checks the file name template and creates the output directory before
+    // generating, reporting any problem in `clip_errors`.
+    fn prepare_output(&mut self) -> Option<naming::NamingTemplate> {
+        self.generated.clear();
+        self.clip_errors.clear();
+        self.anki_status.clear();
+
+        let template = match naming::NamingTemplate::parse(&self.naming_template) {
+            Ok(template) => template,
+            Err(error) => {
+                self.clip_errors
+                    .push(format!("Invalid file name template {}", error));
+                return None;
+            }
+        };
+        if let Err(error) = fs::create_dir_all(&self.output_dir) {
+            self.clip_errors
+                .push(format!("Unable to create {}: {}", self.output_dir, error));
+            return None;
+        }
+        Some(template)
+    }
+
+    fn generate_clips(&mut self) {
+        let Some(template) = self.prepare_output() else {
+            return;
+        };
+        let deck_name = deck_name_for(&self.video_path);
+        let job = batch::EpisodeJob {
+            video_path: self.video_path.clone(),
+            naming: naming::MediaNaming {
+                template,
+                deck: deck_name.clone(),
+                video_path: self.video_path.clone(),
+                episode: None,
+            },
+            clips: self.selected_clips().cloned().collect(),
+            language: probe::subtitle_language(&self.subtitle_path),
+        };
+        self.start_generation(deck_name, vec![job]);
+    }
+
+    // Generates every matched episode into one deck named after the video
+    // directory. Media names always include the episode, or the video stem
+    // for videos without an episode number, so they cannot collide.
+    fn generate_batch(&mut self) {
+        let Some(template) = self.prepare_output() else {
+            return;
+        };
+        let template = template.with_episode_prefix();
+        let deck_name = Path::new(&self.batch_video_dir)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("subs2srs")
+            .to_string();
+
+        let mut jobs = Vec::new();
+        for episode in &self.batch_episodes {
+            let Some(subtitle) = &episode.subtitle else {
+                continue;
+            };
+            let subtitle = subtitle.display().to_string();
+            let clips = subtitles::from_file(&subtitle)
+                .and_then(|clips| Ok(timing::TimingOptions::load(&subtitle)?.apply(clips)?));
+            let clips = match clips {
+                Ok(clips) => clips,
+                Err(error) => {
+                    self.clip_errors
+                        .push(format!("Unable to parse {}: {}", subtitle, error));
+                    continue;
+                }
+            };
+            let video_path = episode.video.display().to_string();
+            jobs.push(batch::EpisodeJob {
+                naming: naming::MediaNaming {
+                    template: template.clone(),
+                    deck: deck_name.clone(),
+                    video_path: video_path.clone(),
+                    episode: episode.number,
+                },
+                video_path,
+                clips: clips
+                    .into_iter()
+                    .filter(|clip| !self.excluded_styles.contains(&clip.style))
+                    .collect(),
+                language: probe::subtitle_language(&subtitle),
+            });
+        }
+        self.start_generation(deck_name, jobs);
+    }
+
+    // Starts generating `jobs` on the runtime started by `main`, so the UI
+    // keeps updating. Progress is collected by `poll_generation`.
+    fn start_generation(&mut self, deck_name: String, jobs: Vec<batch::EpisodeJob>) {
+        let total = jobs.iter().map(|job| job.clips.len()).sum();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let task = tokio::spawn(batch::process_episodes(
+            jobs,
+            self.output_dir.clone(),
+            self.clip_options.clone(),
+            self.concurrency,
+            self.condensed_audio.then_some(self.condensed),
+            tx,
+        ));
+
+        self.generation = Some(Generation {
+            total,
+            completed: 0,
+            output_dir: self.output_dir.clone(),
+            deck_name,
+            events: rx,
+            task,
+        });
+    }
+
+    fn match_batch(&mut self) {
+        self.batch_episodes.clear();
+        self.batch_subtitles.clear();
+        let videos = batch::list_files(Path::new(&self.batch_video_dir), batch::VIDEO_EXTENSIONS);
+        let subtitles = batch::list_files(
+            Path::new(&self.batch_subtitle_dir),
+            batch::SUBTITLE_EXTENSIONS,
+        );
+        let (videos, subtitles) = match (videos, subtitles) {
+            (Ok(videos), Ok(subtitles)) => (videos, subtitles),
+            (Err(error), _) | (_, Err(error)) => {
+                self.batch_status = format!("Unable to list files: {}", error);
+                return;
+            }
+        };
+
+        self.batch_episodes = batch::match_episodes(&videos, &subtitles);
+        self.batch_subtitles = subtitles;
+        let matched = self
+            .batch_episodes
+            .iter()
+            .filter(|episode| episode.subtitle.is_some())
+            .count();
+        self.batch_status = format!(
+            "Matched {} of {} videos, check the pairs before generating",
+            matched,
+            videos.len()
+        );
+    }
+
+    // Collects the clips finished since the last frame, and exports the deck
+    // once every clip is done.
+    fn poll_generation(&mut self) {
+        let Some(generation) = &mut self.generation else {
+            return;
+        };
+        // Check before draining, so no event can arrive after the last drain.
+        let finished = generation.task.is_finished();
+        for event in generation.events.try_iter() {
+            match event {
+                scheduler::ClipEvent::Generated(clip) => {
+                    generation.completed += 1;
+                    self.generated.push(clip);
+                }
+                scheduler::ClipEvent::Failed { index, error } => {
+                    generation.completed += 1;
+                    self.clip_errors.push(format!("Clip {}: {}", index, error));
+                }
+                scheduler::ClipEvent::Condensed {
+                    video_path,
+                    result: Err(error),
+                } => self.clip_errors.push(format!(
+                    "Unable to condense the audio of {}: {}",
+                    video_path, error
+                )),
+                scheduler::ClipEvent::Condensed { .. } => {}
+            }
+        }
+        if !finished {
+            return;
         }
 
-        for clip in &self.clips {
-            process_clip(
-                self.video_path.clone(),
-                output_dir.to_string(),
-                clip.clone(),
-                self.tx.clone(),
+        // The task is finished, so this does not block. Its results are in the
+        // order of the subtitles, unlike the events.
+        let generation = self.generation.take().unwrap();
+        self.generated = match block_on(generation.task) {
+            Ok(results) => results.into_iter().filter_map(Result::ok).collect(),
+            Err(error) => {
+                self.clip_errors
+                    .push(format!("Generating clips failed: {}", error));
+                Vec::new()
+            }
+        };
+        let export_errors = export_deck(
+            &generation.deck_name,
+            &self.generated,
+            Path::new(&generation.output_dir),
+        );
+        self.clip_errors.extend(export_errors);
+    }
+
+    // Stops generating clips, killing any running `ffmpeg` processes. The clips
+    // that were already generated are kept, but no deck is exported.
+    fn cancel_generation(&mut self) {
+        if let Some(generation) = self.generation.take() {
+            generation.task.abort();
+            self.clip_errors.push(format!(
+                "Cancelled after {} of {} clips",
+                generation.completed, generation.total
+            ));
+        }
+    }
+
+    // The clips whose style has not been excluded, e.g. to skip signs and karaoke.
+    fn selected_clips(&self) -> impl Iterator<Item = &SubtitleClip> {
+        self.clips
+            .iter()
+            .filter(|clip| !self.excluded_styles.contains(&clip.style))
+    }
+
+    fn send_to_anki(&mut self) {
+        let deck_name = deck_name_for(&self.video_path);
+        let options = ankiconnect::NoteOptions {
+            tags: vec!["subs2srs".to_string(), tsv::tag_for(&deck_name)],
+            deck: deck_name,
+            note_type: self.anki_note_type.clone(),
+            fields: ankiconnect::FieldMapping {
+                video: self
+                    .generated
+                    .iter()
+                    .any(|generated| generated.video_path.is_some())
+                    .then(|| "Video".to_string()),
+                ..Default::default()
+            },
+        };
+
+        let clips = self.generated.clone();
+        self.anki_task = Some(tokio::spawn(async move {
+            ankiconnect::AnkiConnect::default()
+                .push_clips(&clips, &options)
+                .await
+                .map_err(|error| error.to_string())
+        }));
+        self.anki_status = format!("Sending {} notes to Anki…", self.generated.len());
+    }
+
+    fn poll_anki(&mut self) {
+        let Some(result) = take_finished(&mut self.anki_task) else {
+            return;
+        };
+        self.anki_status = match result {
+            Ok(Ok(outcomes)) => {
+                let count = |wanted: fn(&ankiconnect::NoteOutcome) -> bool| {
+                    outcomes.iter().filter(|outcome| wanted(outcome)).count()
+                };
+                format!(
+                    "Added {} notes, skipped {} duplicates, {} failed",
+                    count(|o| matches!(o, ankiconnect::NoteOutcome::Added(_))),
+                    count(|o| matches!(o, ankiconnect::NoteOutcome::Duplicate)),
+                    count(|o| matches!(o, ankiconnect::NoteOutcome::Failed(_))),
+                )
+            }
+            Ok(Err(error)) => format!("Unable to reach AnkiConnect: {}", error),
+            Err(error) => format!("Sending to Anki failed: {}", error),
+        };
+    }
+
+    // Starts listing the text subtitle tracks of the video. They are collected
+    // by `poll_embedded_subtitles`.
+    fn find_embedded_subtitles(&mut self) {
+        self.embedded_streams.clear();
+        self.embedded_status = "Reading tracks…".to_string();
+        self.embedded_streams_task = Some(tokio::spawn(probe::subtitle_streams(
+            self.video_path.clone(),
+        )));
+    }
+
+    // Probes the video again in the background when its path changes, so the
+    // file panel always describes the current video. Paths that are still
+    // being typed are skipped. The result is collected by `poll_media_info`.
+    fn refresh_media_info(&mut self) {
+        if self.video_path == self.media_info_path {
+            return;
+        }
+        self.media_info_path = self.video_path.clone();
+        // The audio track picked for another video may not exist in this one.
+        self.clip_options.audio_stream = None;
+        self.audio_streams.clear();
+        self.media_info = None;
+        if let Some(task) = self.media_info_task.take() {
+            task.abort();
+        }
+        if !Path::new(&self.video_path).is_file() {
+            return;
+        }
+        self.media_info_task = Some(tokio::spawn(probe::inspect(self.video_path.clone())));
+    }
+
+    fn poll_media_info(&mut self) {
+        let Some(result) = take_finished(&mut self.media_info_task) else {
+            return;
+        };
+        let info = match result {
+            Ok(info) => info.map_err(|error| error.to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+        if let Ok(info) = &info {
+            self.audio_streams = info.audio.clone();
+        }
+        self.media_info = Some(info);
+    }
+
+    // Extracts an embedded track to a temporary file and uses it as the subtitle file.
+    fn use_embedded_subtitles(&mut self, stream: probe::SubtitleStream) {
+        let output_dir = std::env::temp_dir().join("subs2srs_tracks");
+        // The language is kept in the name so the matching audio track is recorded.
+        let output = output_dir.join(format!(
+            "{}.{}.{}{}",
+            deck_name_for(&self.video_path),
+            stream.index,
+            stream
+                .language
+                .as_ref()
+                .map(|language| format!("{}.", language))
+                .unwrap_or_default(),
+            stream.extension()
+        ));
+        let output = output.to_str().unwrap().to_string();
+
+        if let Err(error) = fs::create_dir_all(&output_dir) {
+            self.embedded_status = format!("Unable to extract track: {}", error);
+            return;
+        }
+        let video_path = self.video_path.clone();
+        self.embedded_extract_task = Some(tokio::spawn(async move {
+            subtitle_track::extract_subtitle_track(stream.index, video_path, output.clone())
+                .await
+                .map(|()| output)
+        }));
+        self.embedded_status = "Extracting track…".to_string();
+    }
+
+    fn poll_embedded_subtitles(&mut self) {
+        if let Some(result) = take_finished(&mut self.embedded_streams_task) {
+            self.embedded_status = match result {
+                Ok(Ok(streams)) => {
+                    self.embedded_streams = streams.into_iter().filter(|s| s.is_text()).collect();
+                    if self.embedded_streams.is_empty() {
+                        "No text subtitle tracks found".to_string()
+                    } else {
+                        String::new()
+                    }
+                }
+                Ok(Err(error)) => format!("Unable to read tracks: {}", error),
+                Err(error) => format!("Reading tracks failed: {}", error),
+            };
+        }
+        if let Some(result) = take_finished(&mut self.embedded_extract_task) {
+            self.embedded_status = match result {
+                Ok(Ok(output)) => {
+                    self.subtitle_path = output;
+                    String::new()
+                }
+                Ok(Err(error)) => format!("Unable to extract track: {}", error),
+                Err(error) => format!("Extracting the track failed: {}", error),
+            };
+        }
+    }
+
+    // Whether a subtitle track is being listed or extracted.
+    fn embedded_busy(&self) -> bool {
+        self.embedded_streams_task.is_some() || self.embedded_extract_task.is_some()
+    }
+
+    // Starts aligning `clips`, once corrected by the timing, to the speech in
+    // the recorded audio track. The shift is added by `poll_sync`.
+    fn start_sync(&mut self, clips: &[SubtitleClip]) {
+        let corrected = match self.timing.apply(clips.to_vec()) {
+            Ok(corrected) => corrected,
+            Err(error) => {
+                self.timing_status = format!("Unable to sync: {}", error);
+                return;
+            }
+        };
+        let video_path = self.video_path.clone();
+        let stream = self.clip_options.audio_stream;
+        let options = self.sync_options;
+        self.sync_lines = corrected.iter().map(|clip| clip.index).collect();
+        // The lines are aligned as they are without the shifts of the last sync.
+        self.line_shifts.clear();
+        self.sync_task = Some(tokio::spawn(async move {
+            sync::synchronize(&video_path, stream, &corrected, options).await
+        }));
+        self.timing_status = "Listening for speech…".to_string();
+    }
+
+    fn poll_sync(&mut self) {
+        let Some(result) = take_finished(&mut self.sync_task) else {
+            return;
+        };
+        self.timing_status = match result {
+            Ok(Ok(alignment)) => {
+                let offset = alignment.offset();
+                self.timing.offset += offset;
+                // The shift of most lines goes into the timing, and the lines
+                // on the other side of a split keep the difference.
+                self.line_shifts = self
+                    .sync_lines
+                    .iter()
+                    .zip(&alignment.offsets)
+                    .filter(|(_, line_offset)| **line_offset != offset)
+                    .map(|(&index, line_offset)| (index, line_offset - offset))
+                    .collect();
+                match alignment.splits() {
+                    0 => format!("Shifted by {:+.2}s to match the audio", offset),
+                    splits => format!(
+                        "Shifted by {:+.2}s with {} splits to match the audio. \
+                         Only the shift is saved with the timing",
+                        offset, splits
+                    ),
+                }
+            }
+            Ok(Err(error)) => format!("Unable to sync: {}", error),
+            Err(error) => format!("Syncing failed: {}", error),
+        };
+    }
+
+    // Shows the timing correction of the subtitle file with a preview of its
+    // first lines, and returns `clips` corrected by it and by the line shifts
+    // of the last sync.
+    fn render_timing(&mut self, ui: &mut egui::Ui, clips: Vec<SubtitleClip>) -> Vec<SubtitleClip> {
+        if self.timing_path != self.subtitle_path {
+            self.timing_path = self.subtitle_path.clone();
+            self.line_shifts.clear();
+            (self.timing, self.timing_status) =
+                match timing::TimingOptions::load(&self.subtitle_path) {
+                    Ok(timing) => (timing, String::new()),
+                    Err(error) => (
+                        timing::TimingOptions::default(),
+                        format!("Unable to load the saved timing: {}", error),
+                    ),
+                };
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Shift");
+            ui.add(time_value(&mut self.timing.offset));
+            ui.label("Frame rate");
+            let frame_rate = &mut self.timing.frame_rate;
+            let label = |rate: Option<timing::FrameRateConversion>| match rate {
+                Some(rate) => rate.to_string(),
+                None => "Unchanged".to_string(),
+            };
+            egui::ComboBox::from_id_salt("frame_rate")
+                .selected_text(label(*frame_rate))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(frame_rate, None, label(None));
+                    for rate in timing::COMMON_FRAME_RATE_CONVERSIONS {
+                        ui.selectable_value(frame_rate, Some(rate), label(Some(rate)));
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            let mut stretch = self.timing.stretch.is_some();
+            ui.checkbox(&mut stretch, "Stretch so line");
+            // Starts from the first and last lines where they are now.
+            let point = |clip: Option<&SubtitleClip>| timing::StretchPoint {
+                line: clip.map_or(1, |clip| clip.index),
+                time: clip.map_or(0.0, |clip| clip.start_time.as_secs_f64()),
+            };
+            let points = self.timing.stretch.get_or_insert_with(|| timing::Stretch {
+                first: point(clips.first()),
+                second: point(clips.last()),
+            });
+            ui.add_enabled_ui(stretch, |ui| {
+                ui.add(egui::DragValue::new(&mut points.first.line));
+                ui.label("starts at");
+                ui.add(time_value(&mut points.first.time));
+                ui.label("and line");
+                ui.add(egui::DragValue::new(&mut points.second.line));
+                ui.label("at");
+                ui.add(time_value(&mut points.second.time));
+            });
+            if !stretch {
+                self.timing.stretch = None;
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save timing for this file").clicked() {
+                self.timing_status = match self.timing.save(&self.subtitle_path) {
+                    Ok(()) => "Saved next to the subtitle file".to_string(),
+                    Err(error) => format!("Unable to save the timing: {}", error),
+                };
+            }
+            if ui.button("Reset").clicked() {
+                self.timing = timing::TimingOptions::default();
+                self.line_shifts.clear();
+            }
+            if ui
+                .add_enabled(
+                    !self.video_path.is_empty() && !clips.is_empty() && self.sync_task.is_none(),
+                    egui::Button::new("Sync to audio"),
+                )
+                .on_hover_text("Shifts the lines onto the speech in the video")
+                .clicked()
+            {
+                self.start_sync(&clips);
+            }
+            ui.label(&self.timing_status);
+        });
+        ui.horizontal(|ui| {
+            let options = &mut self.sync_options;
+            ui.label("Sync shifts by up to");
+            ui.add(
+                egui::DragValue::new(&mut options.max_offset)
+                    .range(1.0..=600.0)
+                    .suffix(" s"),
             );
+            let mut splits = options.split_penalty.is_some();
+            ui.checkbox(&mut splits, "Allow splits")
+                .on_hover_text("Lets the shift change partway, as when a scene was cut");
+            let penalty = options.split_penalty.get_or_insert(2.0);
+            ui.add_enabled(
+                splits,
+                egui::DragValue::new(penalty)
+                    .range(0.0..=60.0)
+                    .speed(0.1)
+                    .suffix(" s"),
+            )
+            .on_hover_text("How many more seconds of speech a split must line up");
+            if !splits {
+                options.split_penalty = None;
+            }
+        });
+
+        let corrected = self.preview_timing(ui, clips);
+        if self.line_shifts.is_empty() {
+            return corrected;
+        }
+        let alignment = sync::Alignment {
+            offsets: corrected
+                .iter()
+                .map(|clip| self.line_shifts.get(&clip.index).copied().unwrap_or(0.0))
+                .collect(),
+        };
+        match alignment.apply(corrected.clone()) {
+            Ok(shifted) => shifted,
+            Err(error) => {
+                ui.colored_label(egui::Color32::RED, error.to_string());
+                corrected
+            }
+        }
+    }
+
+    // Pairs the clips with the lines of the native subtitle file. The file is
+    // only read when its path changes, and the clips are only paired again
+    // when they or the pairing options change.
+    fn pair_natives(&mut self, ui: &mut egui::Ui) {
+        let path = &self.native_subtitle_path;
+        let natives = match &mut self.natives {
+            Some(natives) if natives.path == *path => natives,
+            natives => {
+                // The native subtitles often come from another release, so
+                // they keep their own saved timing.
+                let lines = subtitles::from_file(path)
+                    .and_then(|lines| Ok(timing::TimingOptions::load(path)?.apply(lines)?))
+                    .map_err(|error| error.to_string());
+                natives.insert(NativeSubtitles {
+                    path: path.clone(),
+                    lines,
+                    pairing: self.pairing,
+                    unpaired: Vec::new(),
+                    paired: Vec::new(),
+                })
+            }
+        };
+        let lines = match &natives.lines {
+            Ok(lines) => lines,
+            Err(error) => {
+                show_parse_error(ui, path, error.as_str().into());
+                return;
+            }
+        };
+        if natives.unpaired != self.clips || natives.pairing != self.pairing {
+            natives.unpaired = self.clips.clone();
+            natives.pairing = self.pairing;
+            natives.paired = self.clips.clone();
+            pairing::pair_subtitles(&mut natives.paired, lines, self.pairing);
+        }
+        self.clips = natives.paired.clone();
+    }
+
+    // Previews the timing correction on the first lines of `clips`, and
+    // returns them corrected by it.
+    fn preview_timing(&self, ui: &mut egui::Ui, clips: Vec<SubtitleClip>) -> Vec<SubtitleClip> {
+        if self.timing.is_identity() {
+            return clips;
+        }
+        let linear = match self.timing.timing(&clips) {
+            Ok(linear) => linear,
+            Err(error) => {
+                ui.colored_label(egui::Color32::RED, error.to_string());
+                return clips;
+            }
+        };
+        egui::Grid::new("timing_preview").show(ui, |ui| {
+            for clip in clips.iter().take(3) {
+                let time = |time: std::time::Duration| timing::format_time(time.as_secs_f64());
+                ui.label(format!("#{}", clip.index));
+                let corrected = match linear.apply(clip.start_time) {
+                    Ok(corrected) => time(corrected),
+                    Err(_) => "out of range".to_string(),
+                };
+                ui.label(format!("{} → {}", time(clip.start_time), corrected));
+                ui.label(clip.text.lines().next().unwrap_or(""));
+                ui.end_row();
+            }
+        });
+        match self.timing.apply(clips.clone()) {
+            Ok(corrected) => corrected,
+            Err(error) => {
+                ui.colored_label(egui::Color32::RED, error.to_string());
+                clips
+            }
         }
     }
 
@@ -128,6 +935,80 @@ impl MyApp {
                     egui::TextEdit::singleline(&mut self.video_path).desired_width(f32::INFINITY),
                 );
             });
+            self.refresh_media_info();
+            match &self.media_info {
+                Some(Ok(info)) => {
+                    ui.label(info.summary());
+                }
+                Some(Err(error)) => {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!("Unable to read video: {}", error),
+                    );
+                }
+                None if self.media_info_task.is_some() => {
+                    ui.label("Reading video…");
+                }
+                None => {}
+            }
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(
+                        !self.video_path.is_empty() && !self.embedded_busy(),
+                        egui::Button::new("Embedded"),
+                    )
+                    .clicked()
+                {
+                    self.find_embedded_subtitles();
+                }
+                let mut selected = None;
+                ui.add_enabled_ui(!self.embedded_busy(), |ui| {
+                    egui::ComboBox::from_id_salt("embedded_subtitles")
+                        .selected_text("Pick an embedded subtitle track")
+                        .width(f32::INFINITY)
+                        .show_ui(ui, |ui| {
+                            for stream in &self.embedded_streams {
+                                if ui.selectable_label(false, stream.label()).clicked() {
+                                    selected = Some(stream.clone());
+                                }
+                            }
+                        });
+                });
+                if let Some(stream) = selected {
+                    self.use_embedded_subtitles(stream);
+                }
+            });
+            if !self.embedded_status.is_empty() {
+                ui.label(&self.embedded_status);
+            }
+            if self.audio_streams.len() > 1 {
+                ui.horizontal(|ui| {
+                    ui.label("Audio track");
+                    let label = |index: Option<u32>| match index {
+                        Some(index) => self
+                            .audio_streams
+                            .iter()
+                            .find(|stream| stream.index == index)
+                            .map_or_else(|| format!("#{}", index), |stream| stream.label()),
+                        None => "Automatic (subtitle language)".to_string(),
+                    };
+                    let mut selected = self.clip_options.audio_stream;
+                    egui::ComboBox::from_id_salt("audio_stream")
+                        .selected_text(label(selected))
+                        .width(f32::INFINITY)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut selected, None, label(None));
+                            for stream in &self.audio_streams {
+                                ui.selectable_value(
+                                    &mut selected,
+                                    Some(stream.index),
+                                    stream.label(),
+                                );
+                            }
+                        });
+                    self.clip_options.audio_stream = selected;
+                });
+            }
             ui.horizontal(|ui| {
                 if ui.button("Subtitle").clicked() {
                     self.subtitle_path = select_file();
@@ -137,39 +1018,498 @@ impl MyApp {
                         .desired_width(f32::INFINITY),
                 );
             });
+            ui.horizontal(|ui| {
+                if ui.button("Output").clicked()
+                    && let Some(folder) = rfd::FileDialog::new().pick_folder()
+                {
+                    self.output_dir = folder.display().to_string();
+                }
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.output_dir).desired_width(f32::INFINITY),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("File names");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.naming_template)
+                        .hint_text("{deck} {episode} {index:04} {start} {end} {video_stem}")
+                        .desired_width(f32::INFINITY),
+                );
+            });
+            if let Err(error) = naming::NamingTemplate::parse(&self.naming_template) {
+                ui.colored_label(egui::Color32::RED, error.to_string());
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Native").clicked() {
+                    self.native_subtitle_path = select_file();
+                }
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.native_subtitle_path)
+                        .hint_text("Optional translation, paired by timing")
+                        .desired_width(f32::INFINITY),
+                );
+            });
+        });
+
+        frame::frame("Batch", ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Videos").clicked()
+                    && let Some(folder) = rfd::FileDialog::new().pick_folder()
+                {
+                    self.batch_video_dir = folder.display().to_string();
+                }
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.batch_video_dir)
+                        .hint_text("A folder of episodes")
+                        .desired_width(f32::INFINITY),
+                );
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Subtitles").clicked()
+                    && let Some(folder) = rfd::FileDialog::new().pick_folder()
+                {
+                    self.batch_subtitle_dir = folder.display().to_string();
+                }
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.batch_subtitle_dir)
+                        .hint_text("A folder of subtitles, matched by episode number")
+                        .desired_width(f32::INFINITY),
+                );
+            });
+            if ui
+                .add_enabled(
+                    !self.batch_video_dir.is_empty() && !self.batch_subtitle_dir.is_empty(),
+                    egui::Button::new("Match"),
+                )
+                .clicked()
+            {
+                self.match_batch();
+            }
+            if !self.batch_status.is_empty() {
+                ui.label(&self.batch_status);
+            }
+
+            if !self.batch_episodes.is_empty() {
+                egui::Grid::new("batch_episodes")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (row, episode) in self.batch_episodes.iter_mut().enumerate() {
+                            ui.label(match episode.number {
+                                Some(number) => format!("E{:02}", number),
+                                None => "?".to_string(),
+                            });
+                            ui.label(display_name(&episode.video));
+                            let selected = match &episode.subtitle {
+                                Some(subtitle) => display_name(subtitle),
+                                None => "Skip".to_string(),
+                            };
+                            egui::ComboBox::from_id_salt(("batch_subtitle", row))
+                                .selected_text(selected)
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut episode.subtitle, None, "Skip");
+                                    for subtitle in &self.batch_subtitles {
+                                        ui.selectable_value(
+                                            &mut episode.subtitle,
+                                            Some(subtitle.clone()),
+                                            display_name(subtitle),
+                                        );
+                                    }
+                                });
+                            ui.end_row();
+                        }
+                    });
+
+                let ready = self.generation.is_none()
+                    && self
+                        .batch_episodes
+                        .iter()
+                        .any(|episode| episode.subtitle.is_some());
+                if ui
+                    .add_enabled(ready, egui::Button::new("Generate Season"))
+                    .clicked()
+                {
+                    self.generate_batch();
+                }
+            }
         });
 
         frame::frame("Subtitles", ui, |ui| {
             if !self.subtitle_path.is_empty() {
-                let items = match srtparse::from_file(&self.subtitle_path) {
-                    Ok(subtitles) => subtitles,
+                let clips = match subtitles::from_file(&self.subtitle_path) {
+                    Ok(clips) => clips,
                     Err(error) => {
-                        let frame = egui::Frame::window(&ui.style())
-                            .shadow(egui::Shadow::NONE)
-                            .fill(egui::Color32::LIGHT_RED)
-                            .stroke(egui::Stroke::new(2.0, egui::Color32::RED));
-                        frame.show(ui, |ui| {
-                            ui.label(format!(
-                                "Unable to parse {} due to error: {}",
-                                &self.subtitle_path, error
-                            ));
-                        });
+                        show_parse_error(ui, &self.subtitle_path, error);
                         Vec::new()
                     }
                 };
-                self.clips = convert_subs_to_clips(&items);
+                self.clips = self.render_timing(ui, clips);
             }
-
-            let generate_button = egui::Button::new("Generate Clips");
-            if ui
-                .add_enabled(
-                    !self.video_path.is_empty() && !self.clips.is_empty(),
-                    generate_button,
-                )
-                .clicked()
+            if let Some(Ok(probe::MediaInfo {
+                duration: Some(duration),
+                ..
+            })) = &self.media_info
             {
-                // TODO: Generate a new Sender and Receiver channel here?
-                self.generate_clips();
+                let past_end = self
+                    .clips
+                    .iter()
+                    .filter(|clip| clip.start_time.as_secs_f64() >= *duration)
+                    .count();
+                if past_end > 0 {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        format!(
+                            "{} lines start after the end of the video and cannot be clipped",
+                            past_end
+                        ),
+                    );
+                }
+            }
+            if !self.native_subtitle_path.is_empty() && !self.clips.is_empty() {
+                self.pair_natives(ui);
+                ui.horizontal(|ui| {
+                    ui.label("Min overlap");
+                    ui.add(
+                        egui::Slider::new(&mut self.pairing.min_native_coverage, 0.0..=1.0)
+                            .text("of native line"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut self.pairing.min_target_coverage, 0.0..=1.0)
+                            .text("of target line"),
+                    );
+                });
+            }
+
+            let styles: BTreeSet<String> = self
+                .clips
+                .iter()
+                .filter(|clip| !clip.style.is_empty())
+                .map(|clip| clip.style.clone())
+                .collect();
+            if !styles.is_empty() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Styles");
+                    for style in styles {
+                        let mut included = !self.excluded_styles.contains(&style);
+                        if ui.checkbox(&mut included, &style).changed() {
+                            if included {
+                                self.excluded_styles.remove(&style);
+                            } else {
+                                self.excluded_styles.insert(style);
+                            }
+                        }
+                    }
+                });
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Parallel jobs");
+                ui.add(egui::DragValue::new(&mut self.concurrency).range(1..=64));
+                let mut single_pass = self.clip_options.lines_per_pass.is_some();
+                ui.checkbox(&mut single_pass, "Extract in one pass per")
+                    .on_hover_text(
+                        "Reads the video once per group of lines instead of once per file",
+                    );
+                let lines = self
+                    .clip_options
+                    .lines_per_pass
+                    .get_or_insert(extraction::DEFAULT_LINES_PER_PASS);
+                ui.add_enabled(
+                    single_pass,
+                    egui::DragValue::new(lines).range(1..=500).suffix(" lines"),
+                );
+                if !single_pass {
+                    self.clip_options.lines_per_pass = None;
+                }
+            });
+            ui.horizontal(|ui| {
+                let padding = &mut self.clip_options.padding;
+                ui.label("Audio padding");
+                ui.add(
+                    egui::DragValue::new(&mut padding.lead_in)
+                        .range(0.0..=5.0)
+                        .speed(0.05)
+                        .prefix("before ")
+                        .suffix("s"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut padding.lead_out)
+                        .range(0.0..=5.0)
+                        .speed(0.05)
+                        .prefix("after ")
+                        .suffix("s"),
+                );
+                ui.checkbox(&mut padding.avoid_neighbours, "Stop at other lines");
+                ui.checkbox(&mut padding.snap_to_silence, "Snap to silence");
+            });
+            ui.horizontal(|ui| {
+                let encoding = &mut self.clip_options.encoding;
+                ui.label("Audio format");
+                let codec = encoding.codec;
+                egui::ComboBox::from_id_salt("audio_codec")
+                    .selected_text(codec.to_string())
+                    .show_ui(ui, |ui| {
+                        for codec in audio::AudioCodec::ALL {
+                            ui.selectable_value(&mut encoding.codec, codec, codec.to_string());
+                        }
+                    });
+                if encoding.codec != codec {
+                    *encoding = audio::AudioEncoding {
+                        sample_rate: encoding.sample_rate,
+                        mono: encoding.mono,
+                        ..audio::AudioEncoding::new(encoding.codec)
+                    };
+                }
+
+                let mp3 = encoding.codec == audio::AudioCodec::Mp3;
+                let mut vbr = encoding.mp3_vbr_quality.is_some();
+                if mp3 && ui.checkbox(&mut vbr, "VBR").changed() {
+                    encoding.mp3_vbr_quality = vbr.then_some(2);
+                }
+                if let (true, Some(quality)) = (mp3, &mut encoding.mp3_vbr_quality) {
+                    ui.add(
+                        egui::DragValue::new(quality)
+                            .range(0..=9)
+                            .prefix("quality "),
+                    )
+                    .on_hover_text("0 is the best quality and 9 the smallest");
+                } else if !encoding.codec.is_lossless() {
+                    ui.add(
+                        egui::DragValue::new(&mut encoding.bitrate)
+                            .range(8..=320)
+                            .suffix(" kbit/s"),
+                    );
+                }
+
+                let rate_label = |rate: Option<u32>| match rate {
+                    Some(rate) => format!("{} Hz", rate),
+                    None => "Original rate".to_string(),
+                };
+                egui::ComboBox::from_id_salt("audio_sample_rate")
+                    .selected_text(rate_label(encoding.sample_rate))
+                    .show_ui(ui, |ui| {
+                        for rate in [None, Some(16000), Some(24000), Some(44100), Some(48000)] {
+                            ui.selectable_value(&mut encoding.sample_rate, rate, rate_label(rate));
+                        }
+                    });
+                ui.checkbox(&mut encoding.mono, "Mono");
+            });
+            ui.horizontal(|ui| {
+                let image = &mut self.clip_options.image;
+                ui.label("Screenshots");
+                egui::ComboBox::from_id_salt("image_format")
+                    .selected_text(image.format.to_string())
+                    .show_ui(ui, |ui| {
+                        for format in screenshot::ImageFormat::ALL {
+                            ui.selectable_value(&mut image.format, format, format.to_string());
+                        }
+                    });
+                if !image.format.is_lossless() {
+                    ui.add(
+                        egui::DragValue::new(&mut image.quality)
+                            .range(1..=100)
+                            .prefix("quality "),
+                    );
+                }
+
+                use screenshot::ImageSize;
+                let size_label = |size: &ImageSize| match size {
+                    ImageSize::Original => "Original size",
+                    ImageSize::Height(_) => "Height",
+                    ImageSize::Width(_) => "Width",
+                    ImageSize::Fit { .. } => "Fit within",
+                };
+                egui::ComboBox::from_id_salt("image_size")
+                    .selected_text(size_label(&image.size))
+                    .show_ui(ui, |ui| {
+                        for size in [
+                            ImageSize::Original,
+                            ImageSize::Height(320),
+                            ImageSize::Width(480),
+                            ImageSize::Fit {
+                                width: 640,
+                                height: 360,
+                            },
+                        ] {
+                            let selected = size_label(&image.size) == size_label(&size);
+                            if ui.selectable_label(selected, size_label(&size)).clicked()
+                                && !selected
+                            {
+                                image.size = size;
+                            }
+                        }
+                    });
+                let pixels = |value| egui::DragValue::new(value).range(16..=4096).suffix("px");
+                match &mut image.size {
+                    ImageSize::Original => {}
+                    ImageSize::Height(value) | ImageSize::Width(value) => {
+                        ui.add(pixels(value));
+                    }
+                    ImageSize::Fit { width, height } => {
+                        ui.add(pixels(width));
+                        ui.label("×");
+                        ui.add(pixels(height));
+                    }
+                }
+                ui.checkbox(&mut image.crop_letterbox, "Crop black bars");
+            });
+            ui.horizontal(|ui| {
+                let image = &mut self.clip_options.image;
+                let mut burn_in = image.subtitle.is_some();
+                if ui.checkbox(&mut burn_in, "Burn in subtitles").changed() {
+                    image.subtitle = burn_in.then(screenshot::SubtitleOverlay::default);
+                }
+                if let Some(overlay) = &mut image.subtitle {
+                    if ui.button("Font").clicked()
+                        && let Some(file) = rfd::FileDialog::new()
+                            .add_filter("Fonts", &["ttf", "otf", "ttc"])
+                            .pick_file()
+                    {
+                        overlay.font = file.display().to_string();
+                    }
+                    ui.add(
+                        egui::TextEdit::singleline(&mut overlay.font)
+                            .hint_text("A font name or file with every character")
+                            .desired_width(200.0),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut overlay.size)
+                            .range(0.02..=0.2)
+                            .speed(0.005)
+                            .prefix("size "),
+                    )
+                    .on_hover_text("The height of the text relative to the screenshot");
+                }
+            });
+            ui.horizontal(|ui| {
+                let mut enabled = self.clip_options.video.is_some();
+                if ui.checkbox(&mut enabled, "Video clips").changed() {
+                    self.clip_options.video = enabled.then(video::VideoOptions::default);
+                }
+                let Some(video) = &mut self.clip_options.video else {
+                    return;
+                };
+                let format = video.format;
+                egui::ComboBox::from_id_salt("video_format")
+                    .selected_text(format.to_string())
+                    .show_ui(ui, |ui| {
+                        for format in video::VideoFormat::ALL {
+                            ui.selectable_value(&mut video.format, format, format.to_string());
+                        }
+                    });
+                if video.format != format {
+                    // Animations need a low frame rate to stay small.
+                    video.frame_rate = video.format.is_animation().then_some(10);
+                }
+                if let Some(max_crf) = video.format.max_crf() {
+                    video.crf = video.crf.min(max_crf);
+                    ui.add(
+                        egui::DragValue::new(&mut video.crf)
+                            .range(0..=max_crf)
+                            .prefix("CRF "),
+                    )
+                    .on_hover_text("Lower is better quality and larger");
+                    ui.checkbox(&mut video.audio, "Audio");
+                }
+                if let screenshot::ImageSize::Height(height) = &mut video.size {
+                    ui.add(
+                        egui::DragValue::new(height)
+                            .range(16..=2160)
+                            .suffix("px high"),
+                    );
+                }
+                let mut fps = video.frame_rate.unwrap_or(0);
+                if ui
+                    .add(egui::DragValue::new(&mut fps).range(0..=60).suffix(" fps"))
+                    .on_hover_text("0 keeps the frame rate of the video")
+                    .changed()
+                {
+                    video.frame_rate = (fps > 0).then_some(fps);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.condensed_audio, "Condensed audio");
+                ui.add_enabled_ui(self.condensed_audio, |ui| {
+                    ui.label("Padding");
+                    ui.add(
+                        egui::DragValue::new(&mut self.condensed.padding)
+                            .range(0.0..=5.0)
+                            .speed(0.05)
+                            .suffix("s"),
+                    );
+                    ui.label("Gap");
+                    ui.add(
+                        egui::DragValue::new(&mut self.condensed.gap)
+                            .range(0.0..=5.0)
+                            .speed(0.05)
+                            .suffix("s"),
+                    );
+                    ui.label("Chapters of");
+                    ui.add(
+                        egui::DragValue::new(&mut self.condensed.chapter_minutes)
+                            .range(0.0..=600.0)
+                            .suffix(" min"),
+                    )
+                    .on_hover_text("0 keeps the whole episode in one file");
+                });
+            });
+
+            if let Some(generation) = &self.generation {
+                let mut cancel = false;
+                ui.horizontal(|ui| {
+                    let fraction = generation.completed as f32 / generation.total.max(1) as f32;
+                    ui.add(
+                        egui::ProgressBar::new(fraction)
+                            .desired_width(ui.available_width() - 80.0)
+                            .text(format!(
+                                "{} of {} clips",
+                                generation.completed, generation.total
+                            )),
+                    );
+                    cancel = ui.button("Cancel").clicked();
+                });
+                if cancel {
+                    self.cancel_generation();
+                } else {
+                    // Keep polling for progress while nothing else triggers a repaint.
+                    ui.ctx()
+                        .request_repaint_after(std::time::Duration::from_millis(100));
+                }
+            } else {
+                let generate_button = egui::Button::new("Generate Clips");
+                if ui
+                    .add_enabled(
+                        !self.video_path.is_empty() && self.selected_clips().next().is_some(),
+                        generate_button,
+                    )
+                    .clicked()
+                {
+                    self.generate_clips();
+                }
+            }
+            for error in &self.clip_errors {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+
+        frame::frame("Anki", ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Note type");
+                ui.text_edit_singleline(&mut self.anki_note_type);
+                if ui
+                    .add_enabled(
+                        self.generation.is_none()
+                            && self.anki_task.is_none()
+                            && !self.generated.is_empty()
+                            && !self.anki_note_type.is_empty(),
+                        egui::Button::new("Send to Anki"),
+                    )
+                    .clicked()
+                {
+                    self.send_to_anki();
+                }
+            });
+            if !self.anki_status.is_empty() {
+                ui.label(&self.anki_status);
             }
         });
     }
@@ -177,16 +1517,115 @@ impl MyApp {
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_generation();
+        self.poll_sync();
+        self.poll_media_info();
+        self.poll_embedded_subtitles();
+        self.poll_anki();
+        // Keeps polling the background tasks while nothing else repaints.
+        if self.sync_task.is_some()
+            || self.media_info_task.is_some()
+            || self.embedded_busy()
+            || self.anki_task.is_some()
+        {
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
         self.render_app(ctx);
     }
 }
 
+fn show_parse_error(ui: &mut egui::Ui, path: &str, error: Box<dyn std::error::Error>) {
+    let frame = egui::Frame::window(ui.style())
+        .shadow(egui::Shadow::NONE)
+        .fill(egui::Color32::LIGHT_RED)
+        .stroke(egui::Stroke::new(2.0, egui::Color32::RED));
+    frame.show(ui, |ui| {
+        ui.label(format!("Unable to parse {} due to error: {}", path, error));
+    });
+}
+
 fn close_on_esc(ctx: &egui::Context) {
     if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
     }
 }
 
+// Writes `{deck_name}.apkg` and `{deck_name}.tsv` into `output_dir`,
+// returning a message for each file that could not be written.
+fn export_deck(deck_name: &str, clips: &[GeneratedClip], output_dir: &Path) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    let deck_path = output_dir.join(format!("{}.apkg", deck_name));
+    if let Err(error) = apkg::export_apkg(deck_name, clips, &deck_path) {
+        errors.push(format!(
+            "Unable to export {}: {}",
+            deck_path.display(),
+            error
+        ));
+    }
+
+    let tags = vec!["subs2srs".to_string(), tsv::tag_for(deck_name)];
+    let tsv_path = output_dir.join(format!("{}.tsv", deck_name));
+    if let Err(error) = tsv::export_tsv(deck_name, &tags, clips, &tsv_path) {
+        errors.push(format!(
+            "Unable to export {}: {}",
+            tsv_path.display(),
+            error
+        ));
+    }
+
+    errors
+}
+
+// Runs `future` to completion from the synchronous UI code, reusing the
+// runtime started by `main` when there is one.
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => tokio::task::block_in_place(|| handle.block_on(future)),
+        Err(_) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to create new tokio runtime")
+            .block_on(future),
+    }
+}
+
+// Takes the output of `task` once it has finished, leaving `None` behind.
+fn take_finished<T>(
+    task: &mut Option<tokio::task::JoinHandle<T>>,
+) -> Option<Result<T, tokio::task::JoinError>> {
+    if !task.as_ref()?.is_finished() {
+        return None;
+    }
+    // The task is finished, so this does not block.
+    Some(block_on(task.take().unwrap()))
+}
+
+// Names the deck after the video's file stem, e.g. `episode01.mkv` becomes `episode01`.
+fn deck_name_for(video_path: &str) -> String {
+    Path::new(video_path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("subs2srs")
+        .to_string()
+}
+
+// The file name of `path` for showing in the UI.
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// A DragValue for a time in seconds, shown and typed as `HH:MM:SS.mmm`.
+fn time_value(time: &mut f64) -> egui::DragValue<'_> {
+    egui::DragValue::new(time)
+        .speed(0.05)
+        .custom_formatter(|time, _| timing::format_time(time))
+        .custom_parser(|text| timing::parse_time(text).ok())
+}
+
 fn select_file() -> String {
     match rfd::FileDialog::new().pick_file() {
         Some(file) => file.display().to_string(),
@@ -244,10 +1683,11 @@ mod tests {
         assert_eq!(clips[0].index, 1);
         assert_eq!(clips[0].start_time, Duration::from_millis(0));
         assert_eq!(clips[0].end_time, Duration::from_millis(2833));
+        assert_eq!(clips[0].text, items[0].text);
     }
 
-    #[test]
-    fn test_ffmpeg_execution() {
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ffmpeg_execution() {
         let output_dir = setup_test_dir();
         let output_dir_str = output_dir.to_str().unwrap();
         let srt_path = get_absolute_path(TEST_SRT);
@@ -259,22 +1699,30 @@ mod tests {
         assert!(clips.len() > 0, "No clips were parsed from the SRT file.");
 
         // Test only the first 3 clips to save time
-        for clip in clips.iter().take(3) {
-            process_clip(
-                video_path.clone(),
-                output_dir_str.to_string(),
-                clip.clone(),
-                tx.clone(),
-            );
+        let naming = naming::MediaNaming::default();
+        let results = scheduler::process_clips(
+            video_path,
+            output_dir_str.to_string(),
+            &naming,
+            ClipOptions::default(),
+            clips.into_iter().take(3).collect(),
+            2,
+            tx,
+        )
+        .await;
+        for result in results {
+            result.expect("failed to process clip");
         }
-        drop(tx);
 
         let output_files = fs::read_dir(output_dir).unwrap().count();
         assert_eq!(output_files, 3 * 2, "Should be two files per clip tested");
 
-        let ffmpeg_command_executions: u32 = rx.iter().sum();
+        let generated = rx
+            .iter()
+            .filter(|event| matches!(event, scheduler::ClipEvent::Generated(_)))
+            .count();
         assert_eq!(
-            ffmpeg_command_executions, 3,
+            generated, 3,
             "three clips should have been converted with ffmpeg"
         );
     }
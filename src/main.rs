@@ -1,4 +1,17 @@
+use clap::Parser;
+use std::process::ExitCode;
+use subs2srs_rs::cli::Cli;
+
 #[tokio::main]
-async fn main() -> eframe::Result {
-    subs2srs_rs::run()
+async fn main() -> ExitCode {
+    match Cli::parse().command {
+        Some(command) => subs2srs_rs::cli::run(command).await,
+        None => match subs2srs_rs::run() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("error: {}", error);
+                ExitCode::FAILURE
+            }
+        },
+    }
 }
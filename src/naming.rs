@@ -0,0 +1,340 @@
+use crate::SubtitleClip;
+use crate::batch::episode_number;
+use crate::error::Error;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+/// The template used when none is configured.
+pub const DEFAULT_TEMPLATE: &str = "{video_stem}_{index:04}_{start}";
+
+/// The characters Anki strips from media file names.
+const ILLEGAL_CHARACTERS: &[char] = &['[', ']', '<', '>', ':', '"', '/', '?', '*', '^', '\\', '|'];
+
+/// NamingTemplate names the media files created for each clip, e.g.
+/// `{deck}_{episode:02}_{index:04}`. The extension is added by the caller.
+///
+/// The supported tokens are `{deck}`, `{episode}`, `{index}`, `{start}`,
+/// `{end}` and `{video_stem}`. `{episode}` and `{index}` can be zero padded
+/// with a width, as in `{index:04}`. Use `{{` and `}}` for literal braces.
+/// A video without an episode number is named by its stem in `{episode}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamingTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Token { token: Token, width: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Deck,
+    Episode,
+    Index,
+    Start,
+    End,
+    VideoStem,
+    /// `E{episode}_`, or `{video_stem}_` without an episode number. Only
+    /// added by `with_episode_prefix`.
+    EpisodePrefix,
+}
+
+impl NamingTemplate {
+    pub fn parse(template: &str) -> Result<Self, Error> {
+        let invalid =
+            |message: String| Error::InvalidArgument(format!("{:?}: {}", template, message));
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let close = rest
+                        .find('}')
+                        .ok_or_else(|| invalid("unclosed {".to_string()))?;
+                    let (name, width) = match rest[..close].split_once(':') {
+                        Some((name, width)) => (name, Some(width)),
+                        None => (&rest[..close], None),
+                    };
+                    let token = match name {
+                        "deck" => Token::Deck,
+                        "episode" => Token::Episode,
+                        "index" => Token::Index,
+                        "start" => Token::Start,
+                        "end" => Token::End,
+                        "video_stem" => Token::VideoStem,
+                        _ => return Err(invalid(format!("unknown token {{{}}}", name))),
+                    };
+                    let width = match width {
+                        None => 0,
+                        Some(width) if matches!(token, Token::Episode | Token::Index) => width
+                            .parse()
+                            .map_err(|_| invalid(format!("invalid width {:?}", width)))?,
+                        Some(_) => return Err(invalid(format!("{{{}}} has no width", name))),
+                    };
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Token { token, width });
+                    chars = rest[close + 1..].chars();
+                }
+                '}' => return Err(invalid("unmatched }".to_string())),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(NamingTemplate { parts })
+    }
+
+    fn render(&self, naming: &MediaNaming, clip: &SubtitleClip) -> String {
+        let video_stem = video_stem(&naming.video_path);
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => name.push_str(literal),
+                Part::Token { token, width } => name.push_str(&match token {
+                    Token::Deck => naming.deck.clone(),
+                    // Falling back to the stem keeps unnumbered videos apart.
+                    Token::Episode => match naming.episode.or_else(|| episode_number(video_stem)) {
+                        Some(episode) => format!("{:0width$}", episode, width = width),
+                        None => video_stem.to_string(),
+                    },
+                    Token::EpisodePrefix => {
+                        match naming.episode.or_else(|| episode_number(video_stem)) {
+                            Some(episode) => format!("E{:0width$}_", episode, width = width),
+                            None => format!("{}_", video_stem),
+                        }
+                    }
+                    Token::Index => format!("{:0width$}", clip.index, width = width),
+                    Token::Start => timestamp(clip.start_time),
+                    Token::End => timestamp(clip.end_time),
+                    Token::VideoStem => video_stem.to_string(),
+                }),
+            }
+        }
+        sanitize(&name)
+    }
+
+    /// Whether names from different videos always differ, because the
+    /// template contains `{episode}` or `{video_stem}`.
+    pub fn names_episode(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(
+                part,
+                Part::Token {
+                    token: Token::Episode | Token::VideoStem | Token::EpisodePrefix,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// Prefixes the template with `E{episode:02}_`, or `{video_stem}_` for a
+    /// video without an episode number, unless it already names the episode,
+    /// so that the media of a season's episodes do not collide.
+    pub fn with_episode_prefix(mut self) -> Self {
+        if !self.names_episode() {
+            self.parts.insert(
+                0,
+                Part::Token {
+                    token: Token::EpisodePrefix,
+                    width: 2,
+                },
+            );
+        }
+        self
+    }
+}
+
+impl Default for NamingTemplate {
+    fn default() -> Self {
+        NamingTemplate::parse(DEFAULT_TEMPLATE).expect("the default template is valid")
+    }
+}
+
+/// MediaNaming is everything needed to name the media of one video's clips.
+#[derive(Debug, Clone, Default)]
+pub struct MediaNaming {
+    pub template: NamingTemplate,
+    pub deck: String,
+    pub video_path: String,
+    /// The episode number, guessed from `video_path` if `None`. Without one,
+    /// the video is named by its stem instead.
+    pub episode: Option<u32>,
+}
+
+impl MediaNaming {
+    /// Names the media of each clip, without an extension. Names that would
+    /// collide, ignoring case, get a `_2`, `_3`, ... suffix.
+    pub(crate) fn stems(&self, clips: &[SubtitleClip]) -> Vec<String> {
+        let mut used = HashSet::new();
+        clips
+            .iter()
+            .map(|clip| {
+                let stem = self.template.render(self, clip);
+                let mut unique = stem.clone();
+                let mut suffix = 1;
+                while !used.insert(unique.to_lowercase()) {
+                    suffix += 1;
+                    unique = format!("{}_{}", stem, suffix);
+                }
+                unique
+            })
+            .collect()
+    }
+
+    /// Names the condensed audio of the video, without an extension.
+    pub(crate) fn condensed_stem(&self) -> String {
+        sanitize(&format!("{}_condensed", video_stem(&self.video_path)))
+    }
+}
+
+fn video_stem(video_path: &str) -> &str {
+    Path::new(video_path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("")
+}
+
+// Formats `time` as `HH.MM.SS.mmm`, which sorts correctly and is safe in file names.
+fn timestamp(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}.{:02}.{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// Replaces the characters Anki rejects, and control characters, with `_`.
+// Trailing dots and spaces are removed since Windows drops them.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if ILLEGAL_CHARACTERS.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let name = name.trim().trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        "clip".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(index: usize, start_ms: u64, end_ms: u64) -> SubtitleClip {
+        SubtitleClip {
+            index,
+            start_time: Duration::from_millis(start_ms),
+            end_time: Duration::from_millis(end_ms),
+            ..Default::default()
+        }
+    }
+
+    fn naming(template: &str) -> MediaNaming {
+        MediaNaming {
+            template: NamingTemplate::parse(template).unwrap(),
+            deck: "Show: Part 1".to_string(),
+            video_path: "/videos/Show.S01E03.1080p.mkv".to_string(),
+            episode: None,
+        }
+    }
+
+    #[test]
+    fn test_render_tokens() {
+        let stems = naming("{deck}_{episode:02}_{index:04}_{start}-{end}_{video_stem}")
+            .stems(&[clip(7, 3_723_045, 3_724_000)]);
+        assert_eq!(
+            stems,
+            ["Show_ Part 1_03_0007_01.02.03.045-01.02.04.000_Show.S01E03.1080p"]
+        );
+        assert_eq!(
+            naming("{{index}}_{index}").stems(&[clip(7, 0, 1)]),
+            ["{index}_7"]
+        );
+    }
+
+    #[test]
+    fn test_stems_are_unique() {
+        let clips = [clip(1, 0, 1), clip(2, 0, 1), clip(3, 0, 1)];
+        assert_eq!(
+            naming("{deck}").stems(&clips),
+            ["Show_ Part 1", "Show_ Part 1_2", "Show_ Part 1_3"]
+        );
+        assert_eq!(naming("a?.").stems(&clips[..1]), ["a_"]);
+        assert_eq!(naming("...").stems(&clips[..1]), ["clip"]);
+    }
+
+    #[test]
+    fn test_with_episode_prefix() {
+        let mut naming = naming("{deck}_{index}");
+        naming.episode = Some(7);
+        naming.template = naming.template.with_episode_prefix();
+        assert_eq!(naming.stems(&[clip(1, 0, 1)]), ["E07_Show_ Part 1_1"]);
+
+        let template = NamingTemplate::default();
+        assert!(template.names_episode());
+        assert_eq!(template.clone().with_episode_prefix(), template);
+    }
+
+    #[test]
+    fn test_unnumbered_episodes_do_not_collide() {
+        let template = NamingTemplate::parse("{deck}_{index}")
+            .unwrap()
+            .with_episode_prefix();
+        let stems = |video_path: &str| {
+            MediaNaming {
+                template: template.clone(),
+                deck: "Movies".to_string(),
+                video_path: video_path.to_string(),
+                episode: None,
+            }
+            .stems(&[clip(1, 0, 1)])
+        };
+        assert_eq!(stems("/videos/Opening.mkv"), ["Opening_Movies_1"]);
+        assert_eq!(stems("/videos/Finale.mkv"), ["Finale_Movies_1"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for template in ["{nope}", "{index", "index}", "{deck:04}", "{index:x}"] {
+            assert!(
+                matches!(
+                    NamingTemplate::parse(template),
+                    Err(Error::InvalidArgument(_))
+                ),
+                "{} should be rejected",
+                template
+            );
+        }
+        NamingTemplate::default();
+    }
+}
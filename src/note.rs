@@ -0,0 +1,144 @@
+use crate::GeneratedClip;
+use std::path::Path;
+
+/// The names of the note fields, in the order they are exported.
+pub(crate) const FIELD_NAMES: [&str; 5] = ["Sentence", "Meaning", "Audio", "Image", "Video"];
+
+/// The extensions of video clips that are silent animations, shown like images.
+const ANIMATION_EXTENSIONS: [&str; 2] = ["gif", "webp"];
+
+/// NoteFields holds the Anki field values for a single generated clip.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct NoteFields {
+    pub(crate) sentence: String,
+    pub(crate) meaning: String,
+    pub(crate) audio: String,
+    pub(crate) image: String,
+    /// Empty unless a video clip was made.
+    pub(crate) video: String,
+}
+
+impl NoteFields {
+    /// Creates the fields for `generated`, referencing its media by file name.
+    pub(crate) fn from_clip(generated: &GeneratedClip) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(NoteFields {
+            sentence: sentence_html(&generated.clip.text),
+            meaning: sentence_html(&generated.clip.meaning),
+            audio: format!("[sound:{}]", file_name(&generated.audio_path)?),
+            image: format!("<img src=\"{}\">", file_name(&generated.screenshot_path)?),
+            video: match &generated.video_path {
+                Some(path) => video_html(path)?,
+                None => String::new(),
+            },
+        })
+    }
+
+    /// Returns the field values in the same order as `FIELD_NAMES`.
+    pub(crate) fn values(&self) -> [&str; 5] {
+        [
+            &self.sentence,
+            &self.meaning,
+            &self.audio,
+            &self.image,
+            &self.video,
+        ]
+    }
+}
+
+// Anki plays videos with a `[sound:]` tag, but animations are images.
+fn video_html(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let name = file_name(path)?;
+    let animated = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| ANIMATION_EXTENSIONS.contains(&extension));
+    Ok(if animated {
+        format!("<img src=\"{}\">", name)
+    } else {
+        format!("[sound:{}]", name)
+    })
+}
+
+// Escapes subtitle text for use in an HTML field. Line breaks become `<br>`.
+fn sentence_html(text: &str) -> String {
+    text.trim()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+/// Returns the final component of `path`, which is how Anki refers to media.
+pub(crate) fn file_name(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| format!("{} has no file name", path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SubtitleClip;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_note_fields_from_clip() {
+        let generated = GeneratedClip {
+            clip: SubtitleClip {
+                index: 7,
+                start_time: Duration::from_secs(1),
+                end_time: Duration::from_secs(2),
+                text: "你好\n世界".to_string(),
+                meaning: "Hello world".to_string(),
+                ..Default::default()
+            },
+            screenshot_path: PathBuf::from("/tmp/out/screenshot_7.png"),
+            audio_path: PathBuf::from("/tmp/out/audio_clip_7.mp3"),
+            video_path: None,
+        };
+
+        let fields = NoteFields::from_clip(&generated).unwrap();
+        assert_eq!(
+            fields.values(),
+            [
+                "你好<br>世界",
+                "Hello world",
+                "[sound:audio_clip_7.mp3]",
+                "<img src=\"screenshot_7.png\">",
+                ""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_note_fields_video() {
+        let mut generated = GeneratedClip {
+            clip: SubtitleClip::default(),
+            screenshot_path: PathBuf::from("/out/clip.png"),
+            audio_path: PathBuf::from("/out/clip.mp3"),
+            video_path: Some(PathBuf::from("/out/clip.webm")),
+        };
+        assert_eq!(
+            NoteFields::from_clip(&generated).unwrap().video,
+            "[sound:clip.webm]"
+        );
+
+        generated.video_path = Some(PathBuf::from("/out/clip.gif"));
+        assert_eq!(
+            NoteFields::from_clip(&generated).unwrap().video,
+            "<img src=\"clip.gif\">"
+        );
+    }
+
+    #[test]
+    fn test_sentence_html() {
+        assert_eq!(
+            sentence_html(" \"a\" < b & c\r\nd "),
+            "&quot;a&quot; &lt; b &amp; c<br>d"
+        );
+    }
+}
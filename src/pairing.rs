@@ -0,0 +1,159 @@
+use crate::SubtitleClip;
+use std::time::Duration;
+
+/// PairingOptions controls how much two lines must overlap to be paired.
+///
+/// A native line is attached to a target line when either threshold is met,
+/// so a long native line can be attached to each of the short target lines it
+/// was split into, and several short native lines can be attached to one long
+/// target line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairingOptions {
+    /// The fraction of the native line that must overlap the target line.
+    pub min_native_coverage: f64,
+    /// The fraction of the target line that must be overlapped by the native line.
+    pub min_target_coverage: f64,
+}
+
+impl Default for PairingOptions {
+    fn default() -> Self {
+        PairingOptions {
+            min_native_coverage: 0.5,
+            min_target_coverage: 0.5,
+        }
+    }
+}
+
+/// Sets the `meaning` of each target clip to the text of the native clips that
+/// overlap it, joined by newlines in time order.
+///
+/// A native line that meets neither threshold for any target line is attached
+/// to the target line it overlaps the most, so translations are not dropped
+/// just because the two subtitle files were timed differently.
+pub fn pair_subtitles(
+    targets: &mut [SubtitleClip],
+    natives: &[SubtitleClip],
+    options: PairingOptions,
+) {
+    let mut natives: Vec<&SubtitleClip> = natives.iter().collect();
+    natives.sort_by_key(|native| (native.start_time, native.end_time));
+
+    let mut matches: Vec<Vec<&str>> = vec![Vec::new(); targets.len()];
+    for native in natives {
+        let mut paired = false;
+        let mut best: Option<(usize, Duration)> = None;
+
+        for (position, target) in targets.iter().enumerate() {
+            let overlap = overlap(target, native);
+            if overlap.is_zero() {
+                continue;
+            }
+            if coverage(overlap, native) >= options.min_native_coverage
+                || coverage(overlap, target) >= options.min_target_coverage
+            {
+                matches[position].push(&native.text);
+                paired = true;
+            }
+            if best.is_none_or(|(_, best)| overlap > best) {
+                best = Some((position, overlap));
+            }
+        }
+
+        if !paired && let Some((position, _)) = best {
+            matches[position].push(&native.text);
+        }
+    }
+
+    for (target, texts) in targets.iter_mut().zip(matches) {
+        target.meaning = texts.join("\n");
+    }
+}
+
+fn overlap(a: &SubtitleClip, b: &SubtitleClip) -> Duration {
+    let start = a.start_time.max(b.start_time);
+    let end = a.end_time.min(b.end_time);
+    end.saturating_sub(start)
+}
+
+// The fraction of `clip` that `overlap` covers. Zero length clips are fully
+// covered by any overlap.
+fn coverage(overlap: Duration, clip: &SubtitleClip) -> f64 {
+    let length = clip.end_time.saturating_sub(clip.start_time);
+    if length.is_zero() {
+        1.0
+    } else {
+        overlap.as_secs_f64() / length.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(start_ms: u64, end_ms: u64, text: &str) -> SubtitleClip {
+        SubtitleClip {
+            start_time: Duration::from_millis(start_ms),
+            end_time: Duration::from_millis(end_ms),
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn meanings(targets: &[SubtitleClip]) -> Vec<&str> {
+        targets
+            .iter()
+            .map(|target| target.meaning.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_pair_subtitles_one_to_one() {
+        let mut targets = vec![clip(1000, 2000, "一"), clip(3000, 4000, "二")];
+        let natives = vec![clip(3100, 4100, "two"), clip(900, 1900, "one")];
+
+        pair_subtitles(&mut targets, &natives, PairingOptions::default());
+        assert_eq!(meanings(&targets), ["one", "two"]);
+    }
+
+    #[test]
+    fn test_pair_subtitles_merged_target() {
+        // One target line covers two native lines.
+        let mut targets = vec![clip(1000, 5000, "一二")];
+        let natives = vec![clip(1000, 2500, "one"), clip(2600, 5000, "two")];
+
+        pair_subtitles(&mut targets, &natives, PairingOptions::default());
+        assert_eq!(meanings(&targets), ["one\ntwo"]);
+    }
+
+    #[test]
+    fn test_pair_subtitles_split_target() {
+        // One native line is split over two target lines.
+        let mut targets = vec![clip(1000, 2500, "一"), clip(2600, 5000, "二")];
+        let natives = vec![clip(1000, 5000, "one two")];
+
+        pair_subtitles(&mut targets, &natives, PairingOptions::default());
+        assert_eq!(meanings(&targets), ["one two", "one two"]);
+    }
+
+    #[test]
+    fn test_pair_subtitles_thresholds() {
+        // The native line meets neither threshold, so it only goes to the
+        // target it overlaps the most.
+        let mut targets = vec![
+            clip(0, 2000, "一"),
+            clip(5000, 10000, "二"),
+            clip(12000, 13000, "三"),
+        ];
+        let natives = vec![clip(1700, 6000, "one")];
+
+        pair_subtitles(&mut targets, &natives, PairingOptions::default());
+        assert_eq!(meanings(&targets), ["", "one", ""]);
+
+        let lenient = PairingOptions {
+            min_native_coverage: 0.05,
+            min_target_coverage: 0.05,
+        };
+        pair_subtitles(&mut targets, &natives, lenient);
+        assert_eq!(meanings(&targets), ["one", "one", ""]);
+    }
+}
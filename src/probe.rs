@@ -0,0 +1,585 @@
+use crate::error::Error;
+use crate::ffmpeg::execute;
+use serde_json::Value;
+use std::path::Path;
+
+/// Codes and names that refer to the same language, in ISO 639-1, ISO 639-2
+/// and English. Only the languages people commonly study are listed.
+const LANGUAGES: &[&[&str]] = &[
+    &["ja", "jpn", "jp", "japanese"],
+    &["en", "eng", "english"],
+    &[
+        "zh", "chi", "zho", "cmn", "chs", "cht", "chinese", "mandarin",
+    ],
+    &["yue", "cantonese"],
+    &["ko", "kor", "korean"],
+    &["fr", "fre", "fra", "french"],
+    &["de", "ger", "deu", "german"],
+    &["es", "spa", "spanish"],
+    &["it", "ita", "italian"],
+    &["pt", "por", "portuguese"],
+    &["ru", "rus", "russian"],
+    &["th", "tha", "thai"],
+    &["vi", "vie", "vietnamese"],
+];
+
+/// Disposition holds the flags a stream is marked with in its container.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Disposition {
+    /// The player picks the stream unless told otherwise.
+    pub default: bool,
+    /// Subtitles that only cover foreign speech and signs.
+    pub forced: bool,
+    /// Subtitles for the deaf and hard of hearing.
+    pub hearing_impaired: bool,
+    /// Audio description of what is on screen.
+    pub visual_impaired: bool,
+    pub commentary: bool,
+    /// A cover image rather than video.
+    pub attached_pic: bool,
+}
+
+impl Disposition {
+    fn parse(stream: &Value) -> Self {
+        let flag = |name: &str| {
+            stream["disposition"][name]
+                .as_i64()
+                .is_some_and(|flag| flag != 0)
+        };
+        Disposition {
+            default: flag("default"),
+            forced: flag("forced"),
+            hearing_impaired: flag("hearing_impaired"),
+            visual_impaired: flag("visual_impaired"),
+            commentary: flag("comment"),
+            attached_pic: flag("attached_pic"),
+        }
+    }
+}
+
+/// VideoStream describes a video track of a media file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoStream {
+    /// The position of the stream among the file's video streams, as used by
+    /// `-map 0:v:<index>`.
+    pub index: u32,
+    pub codec: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// The average frame rate, in frames per second.
+    pub frame_rate: Option<f64>,
+    pub disposition: Disposition,
+}
+
+/// Chapter is a named part of a media file, in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+/// MediaInfo is what `ffprobe` knows about a media file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    /// The container format, e.g. `matroska,webm`.
+    pub format: String,
+    /// The duration in seconds, which some containers do not record.
+    pub duration: Option<f64>,
+    pub video: Vec<VideoStream>,
+    pub audio: Vec<AudioStream>,
+    pub subtitles: Vec<SubtitleStream>,
+    pub chapters: Vec<Chapter>,
+}
+
+impl MediaInfo {
+    /// The frame rate of the first video stream that is not a cover image.
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.video
+            .iter()
+            .find(|stream| !stream.disposition.attached_pic)
+            .and_then(|stream| stream.frame_rate)
+    }
+
+    /// A one line description, e.g. `23:40, 1920x1080 at 23.976 fps, 2 audio
+    /// tracks, 3 subtitle tracks, 5 chapters`.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(duration) = self.duration {
+            let seconds = duration as u64;
+            parts.push(if seconds >= 3600 {
+                format!(
+                    "{}:{:02}:{:02}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                )
+            } else {
+                format!("{}:{:02}", seconds / 60, seconds % 60)
+            });
+        }
+        if let Some(video) = self
+            .video
+            .iter()
+            .find(|stream| !stream.disposition.attached_pic)
+        {
+            let mut description = match (video.width, video.height) {
+                (Some(width), Some(height)) => format!("{}x{}", width, height),
+                _ => video.codec.clone(),
+            };
+            if let Some(frame_rate) = video.frame_rate {
+                // Trims 24.000 to 24 but keeps 23.976.
+                let frame_rate = format!("{:.3}", frame_rate);
+                let frame_rate = frame_rate.trim_end_matches('0').trim_end_matches('.');
+                description.push_str(&format!(" at {} fps", frame_rate));
+            }
+            parts.push(description);
+        }
+        let count = |count: usize, noun: &str| {
+            format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+        };
+        parts.push(count(self.audio.len(), "audio track"));
+        parts.push(count(self.subtitles.len(), "subtitle track"));
+        if !self.chapters.is_empty() {
+            parts.push(count(self.chapters.len(), "chapter"));
+        }
+        parts.join(", ")
+    }
+}
+
+/// SubtitleStream describes a subtitle track embedded in a media file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleStream {
+    /// The position of the stream among the file's subtitle streams, as used
+    /// by `-map 0:s:<index>`.
+    pub index: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub disposition: Disposition,
+}
+
+impl SubtitleStream {
+    /// Whether the stream holds text, as opposed to images like PGS or VobSub
+    /// which cannot be converted to a subtitle file.
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self.codec.as_str(),
+            "subrip" | "srt" | "ass" | "ssa" | "webvtt" | "mov_text" | "text"
+        )
+    }
+
+    /// The file extension to extract the stream to, keeping ASS styling and
+    /// WebVTT as-is and converting everything else to SRT.
+    pub fn extension(&self) -> &'static str {
+        match self.codec.as_str() {
+            "ass" | "ssa" => "ass",
+            "webvtt" => "vtt",
+            _ => "srt",
+        }
+    }
+
+    /// A human readable description, e.g. `#1 jpn "Full Subtitles" (ass)`.
+    pub fn label(&self) -> String {
+        let mut label = format!("#{}", self.index);
+        if let Some(language) = &self.language {
+            label.push_str(&format!(" {}", language));
+        }
+        if let Some(title) = &self.title {
+            label.push_str(&format!(" \"{}\"", title));
+        }
+        label.push_str(&format!(" ({})", self.codec));
+        label
+    }
+}
+
+/// AudioStream describes an audio track of a media file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioStream {
+    /// The position of the stream among the file's audio streams, as used by
+    /// `-map 0:a:<index>`.
+    pub index: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub channels: Option<u32>,
+    pub disposition: Disposition,
+}
+
+impl AudioStream {
+    /// A human readable description, e.g. `#1 jpn "Original" (aac, 2 channels)`.
+    pub fn label(&self) -> String {
+        let mut label = format!("#{}", self.index);
+        if let Some(language) = &self.language {
+            label.push_str(&format!(" {}", language));
+        }
+        if let Some(title) = &self.title {
+            label.push_str(&format!(" \"{}\"", title));
+        }
+        match self.channels {
+            Some(channels) => label.push_str(&format!(" ({}, {} channels)", self.codec, channels)),
+            None => label.push_str(&format!(" ({})", self.codec)),
+        }
+        label
+    }
+}
+
+/// Inspects `input` with `ffprobe`, reading its duration, streams and chapters.
+pub async fn inspect(input: String) -> Result<MediaInfo, Error> {
+    if input.is_empty() {
+        return Err(Error::InvalidArgument("input path is empty".to_string()));
+    }
+
+    let stdout = execute(
+        "ffprobe",
+        [
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+            "-show_chapters",
+            &input,
+        ],
+    )
+    .await?;
+    let probe = serde_json::from_slice(&stdout).map_err(|error| {
+        Error::InvalidOutput(format!("ffprobe printed invalid JSON: {}", error))
+    })?;
+    parse_media_info(&probe)
+}
+
+/// Lists the subtitle streams in `input` using `ffprobe`.
+pub async fn subtitle_streams(input: String) -> Result<Vec<SubtitleStream>, Error> {
+    Ok(inspect(input).await?.subtitles)
+}
+
+/// Lists the audio streams in `input` using `ffprobe`.
+pub async fn audio_streams(input: String) -> Result<Vec<AudioStream>, Error> {
+    Ok(inspect(input).await?.audio)
+}
+
+/// Checks that a clip from `start` to `end` can be cut from an input lasting
+/// `duration` seconds, if known. Clips may run past the end, since they are
+/// cut short there, but must start before it.
+pub(crate) fn check_clip_range(start: f64, end: f64, duration: Option<f64>) -> Result<(), Error> {
+    if start < 0.0 || end <= start {
+        return Err(Error::InvalidTimeRange { start, end });
+    }
+    match duration {
+        Some(duration) if start >= duration => Err(Error::PastEnd { start, duration }),
+        _ => Ok(()),
+    }
+}
+
+/// Picks the first audio stream in `language`, or `None` if no stream is in
+/// that language.
+pub fn preferred_audio_stream(streams: &[AudioStream], language: &str) -> Option<u32> {
+    streams
+        .iter()
+        .find(|stream| {
+            stream
+                .language
+                .as_deref()
+                .is_some_and(|stream_language| same_language(stream_language, language))
+        })
+        .map(|stream| stream.index)
+}
+
+/// Guesses the language of a subtitle file from a suffix like the `ja` in
+/// `Show.S01E01.ja.srt` or the `Japanese` in `Show [Japanese].ass`.
+pub fn subtitle_language(path: &str) -> Option<String> {
+    let stem = Path::new(path).file_stem()?.to_str()?;
+    stem.rsplit(['.', '[', ']', '(', ')', '_', ' '])
+        .filter(|word| !word.is_empty())
+        .take(3)
+        .find(|word| language_group(word).is_some())
+        .map(str::to_string)
+}
+
+/// Whether two language codes or names refer to the same language, e.g. `ja`
+/// and `jpn`.
+pub fn same_language(a: &str, b: &str) -> bool {
+    match (language_group(a), language_group(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.eq_ignore_ascii_case(b),
+    }
+}
+
+fn language_group(code: &str) -> Option<usize> {
+    let code = code.to_ascii_lowercase();
+    // Regional variants like `pt-BR` or `zh_Hans` count as their language.
+    let code = code.split(['-', '_']).next().unwrap_or("");
+    LANGUAGES.iter().position(|names| names.contains(&code))
+}
+
+// Parses a rate like `24000/1001`, where `0/0` means unknown.
+fn parse_rate(rate: &str) -> Option<f64> {
+    let (numerator, denominator) = rate.split_once('/')?;
+    let numerator: f64 = numerator.parse().ok()?;
+    let denominator: f64 = denominator.parse().ok()?;
+    (numerator > 0.0 && denominator > 0.0).then(|| numerator / denominator)
+}
+
+// Reads a number ffprobe prints as a string, like `"1420.5"`, where `N/A`
+// means unknown.
+fn parse_seconds(value: &Value) -> Option<f64> {
+    value
+        .as_str()?
+        .parse()
+        .ok()
+        .filter(|seconds: &f64| seconds.is_finite())
+}
+
+fn parse_media_info(probe: &Value) -> Result<MediaInfo, Error> {
+    let streams = probe["streams"]
+        .as_array()
+        .ok_or_else(|| Error::InvalidOutput("ffprobe printed no streams".to_string()))?;
+    let of_type = |codec_type: &'static str| {
+        streams
+            .iter()
+            .filter(move |stream| stream["codec_type"] == codec_type)
+            .enumerate()
+            .map(|(index, stream)| (index as u32, stream))
+    };
+    let tag = |stream: &Value, name: &str| stream["tags"][name].as_str().map(str::to_string);
+    let codec = |stream: &Value| {
+        stream["codec_name"]
+            .as_str()
+            .unwrap_or("unknown")
+            .to_string()
+    };
+    let number = |stream: &Value, name: &str| stream[name].as_u64().map(|number| number as u32);
+
+    Ok(MediaInfo {
+        format: probe["format"]["format_name"]
+            .as_str()
+            .unwrap_or("unknown")
+            .to_string(),
+        duration: parse_seconds(&probe["format"]["duration"]),
+        video: of_type("video")
+            .map(|(index, stream)| VideoStream {
+                index,
+                codec: codec(stream),
+                width: number(stream, "width"),
+                height: number(stream, "height"),
+                frame_rate: stream["avg_frame_rate"]
+                    .as_str()
+                    .and_then(parse_rate)
+                    .or_else(|| stream["r_frame_rate"].as_str().and_then(parse_rate)),
+                disposition: Disposition::parse(stream),
+            })
+            .collect(),
+        audio: of_type("audio")
+            .map(|(index, stream)| AudioStream {
+                index,
+                codec: codec(stream),
+                language: tag(stream, "language"),
+                title: tag(stream, "title"),
+                channels: number(stream, "channels"),
+                disposition: Disposition::parse(stream),
+            })
+            .collect(),
+        subtitles: of_type("subtitle")
+            .map(|(index, stream)| SubtitleStream {
+                index,
+                codec: codec(stream),
+                language: tag(stream, "language"),
+                title: tag(stream, "title"),
+                disposition: Disposition::parse(stream),
+            })
+            .collect(),
+        chapters: probe["chapters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|chapter| {
+                Some(Chapter {
+                    start: parse_seconds(&chapter["start_time"])?,
+                    end: parse_seconds(&chapter["end_time"])?,
+                    title: tag(chapter, "title"),
+                })
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_subtitle_streams() {
+        let probe = json!({
+            "streams": [
+                { "index": 0, "codec_name": "h264", "codec_type": "video" },
+                {
+                    "index": 2,
+                    "codec_name": "ass",
+                    "codec_type": "subtitle",
+                    "disposition": { "default": 1, "forced": 0 },
+                    "tags": { "language": "jpn", "title": "Full Subtitles" },
+                },
+                {
+                    "index": 3,
+                    "codec_name": "hdmv_pgs_subtitle",
+                    "codec_type": "subtitle",
+                },
+            ]
+        });
+
+        let streams = parse_media_info(&probe).unwrap().subtitles;
+        assert_eq!(
+            streams,
+            [
+                SubtitleStream {
+                    index: 0,
+                    codec: "ass".to_string(),
+                    language: Some("jpn".to_string()),
+                    title: Some("Full Subtitles".to_string()),
+                    disposition: Disposition {
+                        default: true,
+                        ..Default::default()
+                    },
+                },
+                SubtitleStream {
+                    index: 1,
+                    codec: "hdmv_pgs_subtitle".to_string(),
+                    language: None,
+                    title: None,
+                    disposition: Disposition::default(),
+                },
+            ]
+        );
+        assert!(streams[0].is_text());
+        assert!(!streams[1].is_text());
+        assert_eq!(streams[0].extension(), "ass");
+        assert_eq!(streams[0].label(), "#0 jpn \"Full Subtitles\" (ass)");
+        assert_eq!(streams[1].label(), "#1 (hdmv_pgs_subtitle)");
+    }
+
+    #[test]
+    fn test_parse_audio_streams() {
+        let probe = json!({
+            "streams": [
+                {
+                    "index": 1,
+                    "codec_name": "eac3",
+                    "codec_type": "audio",
+                    "channels": 6,
+                    "tags": { "language": "eng", "title": "English Dub" },
+                },
+                {
+                    "index": 2,
+                    "codec_name": "aac",
+                    "codec_type": "audio",
+                    "channels": 2,
+                    "tags": { "language": "jpn" },
+                },
+            ]
+        });
+
+        let streams = parse_media_info(&probe).unwrap().audio;
+        assert_eq!(streams[1].index, 1);
+        assert_eq!(
+            streams[0].label(),
+            "#0 eng \"English Dub\" (eac3, 6 channels)"
+        );
+        assert_eq!(preferred_audio_stream(&streams, "ja"), Some(1));
+        assert_eq!(preferred_audio_stream(&streams, "English"), Some(0));
+        assert_eq!(preferred_audio_stream(&streams, "ko"), None);
+    }
+
+    #[test]
+    fn test_parse_media_info() {
+        let probe = json!({
+            "streams": [
+                {
+                    "index": 0,
+                    "codec_name": "h264",
+                    "codec_type": "video",
+                    "width": 1920,
+                    "height": 1080,
+                    "r_frame_rate": "24000/1001",
+                    "avg_frame_rate": "24000/1001",
+                },
+                { "index": 1, "codec_name": "aac", "codec_type": "audio" },
+                {
+                    "index": 2,
+                    "codec_name": "mjpeg",
+                    "codec_type": "video",
+                    "avg_frame_rate": "0/0",
+                    "disposition": { "attached_pic": 1 },
+                },
+            ],
+            "chapters": [
+                { "start_time": "0.000000", "end_time": "90.500000", "tags": { "title": "Opening" } },
+                { "start_time": "90.500000", "end_time": "1420.480000" },
+            ],
+            "format": { "format_name": "matroska,webm", "duration": "1420.480000" },
+        });
+
+        let info = parse_media_info(&probe).unwrap();
+        assert_eq!(info.format, "matroska,webm");
+        assert_eq!(info.duration, Some(1420.48));
+        assert_eq!(info.video.len(), 2);
+        assert_eq!(info.video[1].frame_rate, None);
+        assert!(info.video[1].disposition.attached_pic);
+        assert!((info.frame_rate().unwrap() - 23.976).abs() < 0.001);
+        assert_eq!(
+            info.chapters[0],
+            Chapter {
+                start: 0.0,
+                end: 90.5,
+                title: Some("Opening".to_string()),
+            }
+        );
+        assert_eq!(
+            info.summary(),
+            "23:40, 1920x1080 at 23.976 fps, 1 audio track, 0 subtitle tracks, 2 chapters"
+        );
+
+        let info = parse_media_info(&json!({ "streams": [], "format": { "duration": "N/A" } }));
+        assert_eq!(info.unwrap().duration, None);
+        assert!(parse_media_info(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_check_clip_range() {
+        assert!(check_clip_range(1.0, 2.0, Some(60.0)).is_ok());
+        assert!(check_clip_range(59.0, 61.0, Some(60.0)).is_ok());
+        assert!(check_clip_range(61.0, 62.0, None).is_ok());
+        assert!(matches!(
+            check_clip_range(60.0, 62.0, Some(60.0)),
+            Err(Error::PastEnd {
+                start: 60.0,
+                duration: 60.0
+            })
+        ));
+        assert!(matches!(
+            check_clip_range(2.0, 1.0, Some(60.0)),
+            Err(Error::InvalidTimeRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_subtitle_language() {
+        assert_eq!(
+            subtitle_language("/s/Show.S01E01.ja.srt").as_deref(),
+            Some("ja")
+        );
+        assert_eq!(
+            subtitle_language("Show [Japanese].ass").as_deref(),
+            Some("Japanese")
+        );
+        assert_eq!(
+            subtitle_language("Show.0.pt-BR.vtt").as_deref(),
+            Some("pt-BR")
+        );
+        assert_eq!(subtitle_language("Show 01.srt"), None);
+        assert!(same_language("zh-Hans", "chi"));
+        assert!(!same_language("ja", "en"));
+    }
+}
@@ -0,0 +1,347 @@
+use crate::audio::AudioRange;
+use crate::error::Error;
+use crate::extraction::{self, PlannedClip};
+use crate::naming::MediaNaming;
+use crate::screenshot::Crop;
+use crate::{
+    ClipOptions, GeneratedClip, SubtitleClip, audio, probe, process_clip, screenshot, silence,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// The number of clips to process at once when no limit is configured.
+pub fn default_concurrency() -> usize {
+    std::thread::available_parallelism()
+        .map(|parallelism| parallelism.get())
+        .unwrap_or(4)
+}
+
+/// ClipEvent is sent by `process_clips` as soon as each clip is finished.
+#[derive(Debug)]
+pub(crate) enum ClipEvent {
+    Generated(GeneratedClip),
+    Failed {
+        index: usize,
+        error: String,
+    },
+    /// The condensed audio of a video was written, or failed. This is not a
+    /// clip, so it does not count towards the clips' progress.
+    Condensed {
+        video_path: String,
+        result: Result<Vec<PathBuf>, String>,
+    },
+}
+
+/// Processes `clips` on the current Tokio runtime, running at most
+/// `concurrency` clips at a time. Media files are named by `naming`, and
+/// generated according to `options`. With `lines_per_pass`, each job extracts
+/// that many lines in a single ffmpeg command instead, and snaps them to
+/// silence with one more.
+///
+/// Returns one result per clip, in the same order as `clips`, so that a
+/// failing clip does not stop the others from being generated. Dropping the
+/// returned future stops the outstanding clips and kills their `ffmpeg`
+/// processes.
+pub(crate) async fn process_clips(
+    input_path: String,
+    output_dir: String,
+    naming: &MediaNaming,
+    options: ClipOptions,
+    clips: Vec<SubtitleClip>,
+    concurrency: usize,
+    events: Sender<ClipEvent>,
+) -> Vec<Result<GeneratedClip, Error>> {
+    assert!(concurrency > 0);
+
+    // Without a duration the padding is only clamped to the neighbouring lines,
+    // and ffmpeg stops at the end of the input by itself.
+    let duration = probe::inspect(input_path.clone())
+        .await
+        .ok()
+        .and_then(|info| info.duration);
+    let ranges = audio::padded_ranges(&clips, &options.padding, duration);
+    // The black bars are found once per video, a third of the way in to skip
+    // any opening titles. Screenshots are left uncropped if that fails.
+    let crop = if options.image.crop_letterbox {
+        let start = duration.map_or(0.0, |duration| duration / 3.0);
+        screenshot::detect_crop(&input_path, start)
+            .await
+            .ok()
+            .flatten()
+    } else {
+        None
+    };
+
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let mut jobs = JoinSet::new();
+    let count = clips.len();
+    let stems = naming.stems(&clips);
+
+    let clips: Vec<ClipJob> = clips
+        .into_iter()
+        .zip(stems)
+        .zip(ranges)
+        .enumerate()
+        .map(|(position, ((clip, stem), (range, bounds)))| ClipJob {
+            position,
+            clip,
+            media_stem: Path::new(&output_dir).join(stem),
+            range,
+            bounds,
+        })
+        .collect();
+    let lines_per_pass = options.lines_per_pass.filter(|&lines| lines > 0);
+    let groups: Vec<Vec<ClipJob>> = match lines_per_pass {
+        // Lines are extracted in order of time, so each pass decodes as little
+        // of the input as possible.
+        Some(lines) => {
+            let mut clips = clips;
+            clips.sort_by(|a, b| a.range.start.total_cmp(&b.range.start));
+            let mut groups = Vec::new();
+            let mut clips = clips.into_iter().peekable();
+            while clips.peek().is_some() {
+                groups.push(clips.by_ref().take(lines).collect());
+            }
+            groups
+        }
+        None => clips.into_iter().map(|clip| vec![clip]).collect(),
+    };
+
+    for group in groups {
+        let permits = permits.clone();
+        let input_path = input_path.clone();
+        let events = events.clone();
+        let options = options.clone();
+        jobs.spawn(async move {
+            let _permit = permits.acquire_owned().await.expect("semaphore closed");
+            let results = if lines_per_pass.is_some() {
+                extract_in_one_pass(&input_path, group, &options, crop, duration).await
+            } else {
+                let mut results = Vec::new();
+                for job in group {
+                    let range = prepare_range(&input_path, &job, &options, duration).await;
+                    results.push(generate_clip(&input_path, job, range, &options, crop).await);
+                }
+                results
+            };
+            for (_, index, result) in &results {
+                // The receiver may be gone if nobody is watching, which is fine.
+                let _ = events.send(match result {
+                    Ok(generated) => ClipEvent::Generated(generated.clone()),
+                    Err(error) => ClipEvent::Failed {
+                        index: *index,
+                        error: error.to_string(),
+                    },
+                });
+            }
+            results
+        });
+    }
+
+    let mut results: Vec<Option<Result<GeneratedClip, Error>>> = (0..count).map(|_| None).collect();
+    while let Some(job) = jobs.join_next().await {
+        for (position, _, result) in job.expect("clip job panicked") {
+            results[position] = Some(result);
+        }
+    }
+    results
+        .into_iter()
+        .map(|result| result.expect("every clip job reports a result"))
+        .collect()
+}
+
+// ClipJob is a clip along with where its media goes and the part of the input
+// it covers.
+struct ClipJob {
+    // The position of the clip in the list passed to `process_clips`.
+    position: usize,
+    clip: SubtitleClip,
+    media_stem: PathBuf,
+    range: AudioRange,
+    // How far `range` may be widened when snapping to silence.
+    bounds: AudioRange,
+}
+
+// The result of a clip, along with its position and subtitle index.
+type ClipResult = (usize, usize, Result<GeneratedClip, Error>);
+
+// Checks that the clip can be cut from the input, and finds the range to
+// record. Clips past the end would otherwise fail inside ffmpeg, or silently
+// write empty files.
+async fn prepare_range(
+    input_path: &str,
+    job: &ClipJob,
+    options: &ClipOptions,
+    duration: Option<f64>,
+) -> Result<AudioRange, Error> {
+    probe::check_clip_range(
+        job.clip.start_time.as_secs_f64(),
+        job.clip.end_time.as_secs_f64(),
+        duration,
+    )?;
+    if options.padding.snap_to_silence {
+        silence::snap_to_silence(input_path, job.range, job.bounds, options.audio_stream).await
+    } else {
+        Ok(job.range)
+    }
+}
+
+// Generates the media of a single clip with its own ffmpeg commands.
+async fn generate_clip(
+    input_path: &str,
+    job: ClipJob,
+    range: Result<AudioRange, Error>,
+    options: &ClipOptions,
+    crop: Option<Crop>,
+) -> ClipResult {
+    // Progress is reported per clip, so the per-command progress from
+    // `record_audio_clip` is discarded.
+    let (progress, _command_progress) = std::sync::mpsc::channel();
+    let index = job.clip.index;
+    let result = match range {
+        Ok(range) => {
+            process_clip(
+                input_path.to_string(),
+                job.clip,
+                job.media_stem,
+                range,
+                options.clone(),
+                crop,
+                progress,
+            )
+            .await
+        }
+        Err(error) => Err(error),
+    };
+    (job.position, index, result)
+}
+
+// Generates the media of `group` with a single ffmpeg command, after looking
+// for silence around all of its clips with another. If either fails, each
+// clip is handled on its own, so that one bad clip only fails itself and
+// every failure has its own error.
+async fn extract_in_one_pass(
+    input_path: &str,
+    group: Vec<ClipJob>,
+    options: &ClipOptions,
+    crop: Option<Crop>,
+    duration: Option<f64>,
+) -> Vec<ClipResult> {
+    let mut results = Vec::new();
+    let mut checked = Vec::new();
+    for job in group {
+        let (start, end) = (job.clip.start_time, job.clip.end_time);
+        match probe::check_clip_range(start.as_secs_f64(), end.as_secs_f64(), duration) {
+            Ok(()) => checked.push(job),
+            Err(error) => results.push((job.position, job.clip.index, Err(error))),
+        }
+    }
+    if checked.is_empty() {
+        return results;
+    }
+
+    let ranges: Vec<(AudioRange, AudioRange)> =
+        checked.iter().map(|job| (job.range, job.bounds)).collect();
+    let snapped = if options.padding.snap_to_silence {
+        silence::snap_all_to_silence(input_path, &ranges, options.audio_stream).await
+    } else {
+        Ok(ranges.iter().map(|&(range, _)| range).collect())
+    };
+    let mut ready = Vec::new();
+    match snapped {
+        Ok(snapped) => ready.extend(checked.into_iter().zip(snapped)),
+        Err(_) => {
+            for job in checked {
+                match prepare_range(input_path, &job, options, duration).await {
+                    Ok(range) => ready.push((job, range)),
+                    Err(error) => results.push((job.position, job.clip.index, Err(error))),
+                }
+            }
+            if ready.is_empty() {
+                return results;
+            }
+        }
+    }
+
+    let planned: Vec<PlannedClip> = ready
+        .iter()
+        .map(|(job, range)| PlannedClip {
+            clip: job.clip.clone(),
+            media_stem: job.media_stem.clone(),
+            range: *range,
+        })
+        .collect();
+    let extracted = match extraction::plan_extraction(input_path, &planned, options, crop) {
+        Ok((command, generated)) => command.run().await.map(|()| generated),
+        Err(error) => Err(error),
+    };
+    match extracted {
+        Ok(generated) => {
+            results.extend(
+                ready
+                    .iter()
+                    .zip(generated)
+                    .map(|((job, _), generated)| (job.position, job.clip.index, Ok(generated))),
+            );
+        }
+        Err(_) => {
+            for (job, range) in ready {
+                results.push(generate_clip(input_path, job, Ok(range), options, crop).await);
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_process_clips_keeps_order_and_failures() {
+        // Clips with an empty time range fail before running ffmpeg, so this
+        // exercises the scheduler without needing any media.
+        let clips: Vec<SubtitleClip> = (1..=5)
+            .map(|index| SubtitleClip {
+                index,
+                start_time: Duration::from_secs(index as u64),
+                end_time: Duration::from_secs(index as u64),
+                ..Default::default()
+            })
+            .collect();
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let naming = MediaNaming::default();
+        let results = process_clips(
+            "input.mkv".into(),
+            "/tmp".into(),
+            &naming,
+            ClipOptions::default(),
+            clips,
+            2,
+            tx,
+        )
+        .await;
+        assert_eq!(results.len(), 5);
+        for (position, result) in results.iter().enumerate() {
+            match result {
+                Err(Error::InvalidTimeRange { start, .. }) => {
+                    assert_eq!(*start, position as f64 + 1.0)
+                }
+                other => panic!("expected InvalidTimeRange, got {:?}", other),
+            }
+        }
+        let failed: Vec<usize> = rx
+            .iter()
+            .map(|event| match event {
+                ClipEvent::Failed { index, .. } => index,
+                other => panic!("expected Failed, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(failed.len(), 5);
+    }
+}
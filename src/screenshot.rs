@@ -1,30 +1,272 @@
-use crate::ffmpeg::FFmpegBuilder;
-use tokio::process::Command;
+use crate::error::Error;
+use crate::ffmpeg::{FFmpegBuilder, escape_filter_value};
+use std::str::FromStr;
 
+/// The font subtitles are burned in with unless another is chosen. It covers
+/// Chinese, Japanese and Korean as well as Latin text.
+pub const DEFAULT_SUBTITLE_FONT: &str = "Noto Sans CJK SC";
+
+/// How many seconds of video `detect_crop` looks at.
+const CROP_DETECT_SECONDS: f64 = 10.0;
+
+/// ImageFormat is the format screenshots are saved in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 4] = [
+        ImageFormat::Png,
+        ImageFormat::Jpeg,
+        ImageFormat::Webp,
+        ImageFormat::Avif,
+    ];
+
+    /// The extension of the files written in this format, without a dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+        }
+    }
+
+    /// Whether the format is lossless, and so ignores the quality.
+    pub fn is_lossless(self) -> bool {
+        self == ImageFormat::Png
+    }
+}
+
+impl std::fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+        })
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpg" => Ok(ImageFormat::Jpeg),
+            name => ImageFormat::ALL
+                .into_iter()
+                .find(|format| format.to_string() == name)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown image format {:?}, expected png, jpeg, webp or avif",
+                        name
+                    )
+                }),
+        }
+    }
+}
+
+/// ImageSize is how screenshots are scaled, keeping their aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageSize {
+    /// Keeps the size of the video.
+    Original,
+    Height(u32),
+    Width(u32),
+    /// Shrinks the image to fit within a box, without ever enlarging it.
+    Fit {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Default for ImageSize {
+    fn default() -> Self {
+        ImageSize::Height(320)
+    }
+}
+
+/// Crop is a rectangle of the video frame, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// SubtitleOverlay burns the line's text into the bottom of the screenshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleOverlay {
+    /// A font family name, or the path of a font file. The font must contain
+    /// every character of the text, since there is no fallback font.
+    pub font: String,
+    /// The height of the text as a fraction of the image height.
+    pub size: f64,
+}
+
+impl Default for SubtitleOverlay {
+    fn default() -> Self {
+        SubtitleOverlay {
+            font: DEFAULT_SUBTITLE_FONT.to_string(),
+            size: 0.07,
+        }
+    }
+}
+
+impl SubtitleOverlay {
+    // A `drawtext` filter that centres `text` near the bottom of the frame,
+    // on a translucent box so it stays readable on bright scenes.
+    fn filter(&self, text: &str) -> String {
+        let font = if is_font_file(&self.font) {
+            format!("fontfile={}", escape_filter_value(&self.font))
+        } else {
+            format!("font={}", escape_filter_value(&self.font))
+        };
+        // The text is drawn exactly, with no `%{...}` expansion.
+        format!(
+            "drawtext={}:expansion=none:text={}:fontsize=h*{}:fontcolor=white:\
+             box=1:boxcolor=black@0.5:boxborderw=8:x=(w-text_w)/2:y=h-text_h-h/20",
+            font,
+            escape_filter_value(text.trim()),
+            self.size
+        )
+    }
+}
+
+fn is_font_file(font: &str) -> bool {
+    let lower = font.to_ascii_lowercase();
+    font.contains(['/', '\\'])
+        || [".ttf", ".otf", ".ttc"]
+            .iter()
+            .any(|ext| lower.ends_with(ext))
+}
+
+/// ImageOptions controls the screenshot taken for each clip. The default is a
+/// 320 pixel high PNG.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageOptions {
+    pub format: ImageFormat,
+    /// From 1 (smallest) to 100 (best). Ignored by PNG.
+    pub quality: u8,
+    pub size: ImageSize,
+    /// Crops the black bars of letterboxed videos, found with `cropdetect`.
+    pub crop_letterbox: bool,
+    /// Burns the line into the screenshot.
+    pub subtitle: Option<SubtitleOverlay>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            format: ImageFormat::Png,
+            quality: 85,
+            size: ImageSize::default(),
+            crop_letterbox: false,
+            subtitle: None,
+        }
+    }
+}
+
+/// Saves the frame at `time` as `output_stem` with the extension of the
+/// format added, cropped to `crop` if given, and returns the path written.
+/// With a subtitle overlay, `text` is drawn onto the frame.
 pub async fn take_screenshot(
     time: f64,
     input: String,
-    output: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    assert!(time >= 0.0);
-    assert_ne!(input, "");
-    assert_ne!(output, "");
-
-    let ffmpeg = FFmpegBuilder::new(input, output)
+    output_stem: String,
+    options: &ImageOptions,
+    crop: Option<Crop>,
+    text: &str,
+) -> Result<String, Error> {
+    // The stem may contain dots, so the extension is appended rather than set.
+    let output = format!("{}.{}", output_stem, options.format.extension());
+    screenshot_builder(input, output.clone(), options, crop, text)
         .seek_to(time)
+        .build()?
+        .run()
+        .await?;
+    Ok(output)
+}
+
+// Sets up everything about a screenshot but where in the input it is taken.
+pub(crate) fn screenshot_builder(
+    input: String,
+    output: String,
+    options: &ImageOptions,
+    crop: Option<Crop>,
+    text: &str,
+) -> FFmpegBuilder {
+    let mut builder = FFmpegBuilder::new(input, output)
+        .overwrite()
         .output_frames_count(1)
-        .scale(320)
         .disable_audio()
-        .build();
+        .encode_image(options.format, options.quality);
+    if let Some(crop) = crop {
+        builder = builder.crop(crop);
+    }
+    if options.size != ImageSize::Original {
+        builder = builder.scale_to(options.size);
+    }
+    if let Some(overlay) = &options.subtitle
+        && !text.trim().is_empty()
+    {
+        builder = builder.video_filter(overlay.filter(text));
+    }
+    builder
+}
 
-    Command::new("ffmpeg")
-        .args(ffmpeg.args())
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .output()
+/// Finds the picture inside any black bars by running `cropdetect` over a few
+/// seconds of the video from `start`. The bars must be black throughout, so a
+/// dark scene alone is not cropped.
+pub(crate) async fn detect_crop(input: &str, start: f64) -> Result<Option<Crop>, Error> {
+    let stderr = crop_detect_builder(input.to_string(), start)
+        .build()?
+        .run_for_stderr()
         .await?;
+    Ok(parse_crop(&String::from_utf8_lossy(&stderr)))
+}
+
+// Runs `cropdetect` over `CROP_DETECT_SECONDS` of the video from `start`,
+// sampling two frames a second, and discards the output.
+fn crop_detect_builder(input: String, start: f64) -> FFmpegBuilder {
+    let start = start.max(0.0);
+    FFmpegBuilder::new(input, "-".to_string())
+        .quiet()
+        .seek_to(start)
+        .end_at(start + CROP_DETECT_SECONDS)
+        .disable_audio()
+        .video_filter("fps=2,cropdetect=round=2".to_string())
+        .null_output()
+}
 
-    Ok(())
+// Reads the last `crop=w:h:x:y` that `cropdetect` printed. Its rectangle
+// covers the picture in every frame seen so far.
+fn parse_crop(stderr: &str) -> Option<Crop> {
+    let line = stderr.lines().rev().find(|line| line.contains("crop="))?;
+    let values = &line[line.rfind("crop=")? + "crop=".len()..];
+    let values: Vec<u32> = values
+        .split_whitespace()
+        .next()?
+        .split(':')
+        .map(|value| value.parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [width, height, x, y] if width > 0 && height > 0 => Some(Crop {
+            width,
+            height,
+            x,
+            y,
+        }),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
@@ -61,13 +303,114 @@ mod tests {
     async fn test_take_screenshot() {
         let time = 10.23;
         let input = get_absolute_path(TEST_VIDEO);
-        let output = format!("{}/screenshot.png", setup_test_dir().to_str().unwrap());
-        let output_path = PathBuf::from(&output);
+        let output = format!("{}/screenshot", setup_test_dir().to_str().unwrap());
+        let output_path = PathBuf::from(format!("{}.png", output));
 
         assert!(!output_path.exists());
-        take_screenshot(time, input, output)
+        let written = take_screenshot(time, input, output, &ImageOptions::default(), None, "")
             .await
             .expect("failed to take screenshot");
+        assert_eq!(PathBuf::from(written), output_path);
         assert!(output_path.exists());
     }
+
+    #[tokio::test]
+    async fn test_take_screenshot_invalid_time() {
+        let input = get_absolute_path(TEST_VIDEO);
+        let output = format!("{}/screenshot", setup_test_dir().to_str().unwrap());
+
+        let result = take_screenshot(-1.0, input, output, &ImageOptions::default(), None, "").await;
+        assert!(matches!(result, Err(Error::InvalidTimeRange { .. })));
+    }
+
+    #[test]
+    fn test_screenshot_builder_overwrites() {
+        // Reruns write the same names, so existing files must be replaced.
+        let args = screenshot_builder(
+            "in.mkv".to_string(),
+            "out.png".to_string(),
+            &ImageOptions::default(),
+            None,
+            "",
+        )
+        .build()
+        .unwrap()
+        .args();
+        assert!(args.iter().any(|arg| arg == "-y"), "{:?}", args);
+    }
+
+    #[test]
+    fn test_crop_detect_builder() {
+        let args = crop_detect_builder("in.mkv".to_string(), 600.0)
+            .build()
+            .unwrap()
+            .args();
+        assert_eq!(
+            args,
+            [
+                "-hide_banner",
+                "-nostats",
+                "-ss",
+                "600.000",
+                "-i",
+                "in.mkv",
+                "-vf",
+                "fps=2,cropdetect=round=2",
+                "-an",
+                "-t",
+                "10.000",
+                "-f",
+                "null",
+                "-",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_crop() {
+        let stderr = "\
+[Parsed_cropdetect_1 @ 0x1] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 x:0 y:140 pts:2 t:1.0 limit:0.094 crop=1920:800:0:140
+[Parsed_cropdetect_1 @ 0x1] x1:0 x2:1919 y1:138 y2:941 w:1920 h:804 x:0 y:138 pts:4 t:2.0 limit:0.094 crop=1920:804:0:138
+[out#0/null @ 0x2] video:1kB audio:0kB
+";
+        assert_eq!(
+            parse_crop(stderr),
+            Some(Crop {
+                width: 1920,
+                height: 804,
+                x: 0,
+                y: 138
+            })
+        );
+        assert_eq!(parse_crop("crop=-1920:-1080:0:0"), None);
+        assert_eq!(parse_crop(""), None);
+    }
+
+    #[test]
+    fn test_subtitle_overlay_filter() {
+        let overlay = SubtitleOverlay {
+            font: "/fonts/NotoSansCJK.ttc".to_string(),
+            size: 0.05,
+        };
+        assert_eq!(
+            overlay.filter(" 你好: 世界 "),
+            "drawtext=fontfile=/fonts/NotoSansCJK.ttc:expansion=none:text=你好\\\\: 世界:\
+             fontsize=h*0.05:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=8:\
+             x=(w-text_w)/2:y=h-text_h-h/20"
+        );
+        assert!(
+            SubtitleOverlay::default()
+                .filter("a")
+                .starts_with("drawtext=font=Noto Sans CJK SC:")
+        );
+    }
+
+    #[test]
+    fn test_image_format_from_str() {
+        for format in ImageFormat::ALL {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert_eq!("JPG".parse(), Ok(ImageFormat::Jpeg));
+        assert!("gif".parse::<ImageFormat>().is_err());
+    }
 }
@@ -0,0 +1,238 @@
+use crate::audio::AudioRange;
+use crate::error::Error;
+use crate::ffmpeg::FFmpegBuilder;
+
+/// How far past each boundary of a clip to look for silence, in seconds.
+const SNAP_WINDOW: f64 = 0.5;
+/// Audio quieter than this counts as silence.
+const NOISE_FLOOR: &str = "-30dB";
+/// The shortest pause that counts as silence, in seconds.
+const MIN_SILENCE: f64 = 0.1;
+
+/// Moves the boundaries of `range` out to the nearest silence, staying within
+/// `bounds`, so the first and last syllables are not cut off.
+///
+/// A boundary already in silence is kept, and boundaries are only ever moved
+/// outwards, so snapping never cuts speech that `range` included. Silence is
+/// looked for in `stream`, or in the default audio stream.
+pub(crate) async fn snap_to_silence(
+    input: &str,
+    range: AudioRange,
+    bounds: AudioRange,
+    stream: Option<u32>,
+) -> Result<AudioRange, Error> {
+    let window = snap_window(range, bounds);
+    if window.end <= window.start {
+        return Ok(range);
+    }
+    let silences = detect_silences(input, window, stream).await?;
+    Ok(snap(range, &silences))
+}
+
+/// Snaps each of `ranges`, given with its bounds, as `snap_to_silence` does,
+/// but looks for silence with a single ffmpeg command over the span of all of
+/// them. The ranges should be close together, since the audio between them is
+/// decoded too.
+pub(crate) async fn snap_all_to_silence(
+    input: &str,
+    ranges: &[(AudioRange, AudioRange)],
+    stream: Option<u32>,
+) -> Result<Vec<AudioRange>, Error> {
+    let windows: Vec<AudioRange> = ranges
+        .iter()
+        .map(|&(range, bounds)| snap_window(range, bounds))
+        .filter(|window| window.end > window.start)
+        .collect();
+    if windows.is_empty() {
+        return Ok(ranges.iter().map(|&(range, _)| range).collect());
+    }
+    let span = AudioRange {
+        start: windows
+            .iter()
+            .map(|window| window.start)
+            .fold(f64::INFINITY, f64::min),
+        end: windows
+            .iter()
+            .map(|window| window.end)
+            .fold(f64::NEG_INFINITY, f64::max),
+    };
+    let silences = detect_silences(input, span, stream).await?;
+    Ok(ranges
+        .iter()
+        .map(|&(range, bounds)| {
+            let window = snap_window(range, bounds);
+            if window.end <= window.start {
+                return range;
+            }
+            snap(range, &within(&silences, window))
+        })
+        .collect())
+}
+
+// The part of the input searched for silence around `range`.
+fn snap_window(range: AudioRange, bounds: AudioRange) -> AudioRange {
+    AudioRange {
+        start: (range.start - SNAP_WINDOW).max(bounds.start),
+        end: (range.end + SNAP_WINDOW).min(bounds.end),
+    }
+}
+
+// Finds the silences in `window` of the input.
+async fn detect_silences(
+    input: &str,
+    window: AudioRange,
+    stream: Option<u32>,
+) -> Result<Vec<AudioRange>, Error> {
+    let stderr = silence_detect_builder(input.to_string(), window, stream)
+        .build()?
+        .run_for_stderr()
+        .await?;
+    Ok(parse_silences(&String::from_utf8_lossy(&stderr), window))
+}
+
+// Runs `silencedetect` over `window` of the input and discards the output.
+// The input is seeked like the clips being snapped, so the silences line up
+// with them.
+fn silence_detect_builder(input: String, window: AudioRange, stream: Option<u32>) -> FFmpegBuilder {
+    let mut builder = FFmpegBuilder::new(input, "-".to_string())
+        .quiet()
+        .seek_to(window.start)
+        .end_at(window.end)
+        .disable_video()
+        .audio_filter(format!(
+            "silencedetect=noise={}:d={}",
+            NOISE_FLOOR, MIN_SILENCE
+        ))
+        .null_output();
+    if let Some(stream) = stream {
+        builder = builder.map_audio_stream(stream);
+    }
+    builder
+}
+
+// Cuts `silences` down to `window`, so that silences found over a wider span
+// snap a range as if only its own window had been searched.
+fn within(silences: &[AudioRange], window: AudioRange) -> Vec<AudioRange> {
+    silences
+        .iter()
+        .map(|silence| AudioRange {
+            start: silence.start.max(window.start),
+            end: silence.end.min(window.end),
+        })
+        .filter(|silence| silence.start <= silence.end)
+        .collect()
+}
+
+// Reads the `silence_start: <t>` and `silence_end: <t>` lines that
+// `silencedetect` prints, which are relative to the start of `window`.
+// Silence that runs past the end of the window ends with it.
+fn parse_silences(stderr: &str, window: AudioRange) -> Vec<AudioRange> {
+    let value = |line: &str, key: &str| -> Option<f64> {
+        let rest = &line[line.find(key)? + key.len()..];
+        rest.split_whitespace().next()?.parse().ok()
+    };
+
+    let mut silences = Vec::new();
+    let mut start = None;
+    for line in stderr.lines() {
+        if let Some(time) = value(line, "silence_start:") {
+            start = Some(window.start + time.max(0.0));
+        } else if let Some(time) = value(line, "silence_end:") {
+            silences.push(AudioRange {
+                start: start.take().unwrap_or(window.start),
+                end: window.start + time,
+            });
+        }
+    }
+    if let Some(start) = start {
+        silences.push(AudioRange {
+            start,
+            end: window.end,
+        });
+    }
+    silences
+}
+
+fn snap(range: AudioRange, silences: &[AudioRange]) -> AudioRange {
+    let in_silence = |time: f64| {
+        silences
+            .iter()
+            .any(|silence| silence.start <= time && time <= silence.end)
+    };
+
+    let mut snapped = range;
+    if !in_silence(range.start)
+        && let Some(silence) = silences
+            .iter()
+            .filter(|silence| silence.end < range.start)
+            .max_by(|a, b| a.end.total_cmp(&b.end))
+    {
+        snapped.start = silence.end;
+    }
+    if !in_silence(range.end)
+        && let Some(silence) = silences
+            .iter()
+            .filter(|silence| silence.start > range.end)
+            .min_by(|a, b| a.start.total_cmp(&b.start))
+    {
+        snapped.end = silence.start;
+    }
+    snapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: f64, end: f64) -> AudioRange {
+        AudioRange { start, end }
+    }
+
+    #[test]
+    fn test_parse_silences() {
+        let stderr = "\
+[silencedetect @ 0x1] silence_start: -0.002
+[silencedetect @ 0x1] silence_end: 0.25 | silence_duration: 0.252
+size=N/A time=00:00:01.00 bitrate=N/A speed=50x
+[silencedetect @ 0x1] silence_start: 1.5
+";
+        assert_eq!(
+            parse_silences(stderr, range(10.0, 12.0)),
+            [range(10.0, 10.25), range(11.5, 12.0)]
+        );
+    }
+
+    #[test]
+    fn test_silence_detect_builder() {
+        let args = silence_detect_builder("in.mkv".to_string(), range(9.5, 12.5), Some(1))
+            .build()
+            .unwrap()
+            .args();
+        assert_eq!(
+            args.join(" "),
+            "-hide_banner -nostats -ss 9.500 -i in.mkv -map 0:a:1 -t 3.000 -vn \
+             -af silencedetect=noise=-30dB:d=0.1 -f null -"
+        );
+    }
+
+    #[test]
+    fn test_within() {
+        let silences = [range(9.0, 9.8), range(10.5, 11.0), range(12.4, 13.0)];
+        assert_eq!(
+            within(&silences, range(9.5, 12.5)),
+            [range(9.5, 9.8), range(10.5, 11.0), range(12.4, 12.5)]
+        );
+        assert!(within(&silences, range(11.2, 12.0)).is_empty());
+    }
+
+    #[test]
+    fn test_snap() {
+        let silences = [range(9.5, 9.8), range(12.4, 12.6)];
+        // Both boundaries are in speech, so they move out to the silences.
+        assert_eq!(snap(range(10.0, 12.0), &silences), range(9.8, 12.4));
+        // Boundaries in silence stay where they are.
+        assert_eq!(snap(range(9.6, 12.5), &silences), range(9.6, 12.5));
+        // Without silence nearby nothing changes.
+        assert_eq!(snap(range(10.0, 12.0), &[]), range(10.0, 12.0));
+    }
+}
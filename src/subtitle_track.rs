@@ -0,0 +1,62 @@
+use crate::error::Error;
+use crate::ffmpeg::FFmpegBuilder;
+
+/// Extracts the `index`th subtitle stream of `input` into `output`.
+///
+/// FFmpeg converts the stream to the format implied by the extension of
+/// `output`, so an ASS track can be written as `.ass` or `.srt`.
+pub async fn extract_subtitle_track(
+    index: u32,
+    input: String,
+    output: String,
+) -> Result<(), Error> {
+    FFmpegBuilder::new(input, output)
+        .overwrite()
+        .map_subtitle_stream(index)
+        .build()?
+        .run()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    const TEST_VIDEO: &str = "videos/Minecraft_1.20生存#1.偏頭.mkv";
+    const TEST_OUTPUT_DIR_BASE: &str = "/tmp/subs2srs_tests";
+
+    // Helper to get absolute path from relative
+    fn get_absolute_path(relative_path: &str) -> String {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join(relative_path)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    // Helper to create a unique test directory
+    fn setup_test_dir() -> PathBuf {
+        let test_run_id = Uuid::new_v4().to_string();
+        let output_dir = PathBuf::from(TEST_OUTPUT_DIR_BASE).join(test_run_id);
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir).unwrap();
+        }
+        fs::create_dir_all(&output_dir).unwrap();
+        output_dir
+    }
+
+    #[tokio::test]
+    async fn test_extract_missing_subtitle_track() {
+        // Extracting a stream that does not exist must fail rather than
+        // silently leaving no file behind.
+        let input = get_absolute_path(TEST_VIDEO);
+        let output = format!("{}/track.srt", setup_test_dir().to_str().unwrap());
+
+        let result = extract_subtitle_track(99, input, output.clone()).await;
+        assert!(result.is_err());
+        assert!(!PathBuf::from(output).exists());
+    }
+}
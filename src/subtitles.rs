@@ -0,0 +1,52 @@
+use crate::{SubtitleClip, ass, convert_subs_to_clips, vtt};
+use std::path::Path;
+
+/// Reads the subtitle file at `path` into clips, picking the parser based on
+/// the file extension. Files that are not recognized are parsed as SRT.
+pub(crate) fn from_file(path: &str) -> Result<Vec<SubtitleClip>, Box<dyn std::error::Error>> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("vtt") => vtt::parse(&std::fs::read_to_string(path)?),
+        Some("ass" | "ssa") => ass::parse(&std::fs::read_to_string(path)?),
+        _ => {
+            let items = srtparse::from_file(path).map_err(|error| error.to_string())?;
+            Ok(convert_subs_to_clips(&items))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const TEST_SRT: &str = "videos/Minecraft_1.20生存#1.偏頭.zh.srt";
+    const TEST_VTT: &str = "videos/Minecraft_1.20生存#1.偏頭.zh.vtt";
+
+    // Helper to get absolute path from relative
+    fn get_absolute_path(relative_path: &str) -> String {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join(relative_path)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_vtt_matches_srt() {
+        let srt = from_file(&get_absolute_path(TEST_SRT)).expect("failed to parse SRT file");
+        let vtt = from_file(&get_absolute_path(TEST_VTT)).expect("failed to parse VTT file");
+        assert!(!srt.is_empty());
+        assert_eq!(srt.len(), vtt.len());
+
+        for (srt, vtt) in srt.iter().zip(&vtt) {
+            assert_eq!(srt.start_time, vtt.start_time, "clip {}", srt.index);
+            assert_eq!(srt.end_time, vtt.end_time, "clip {}", srt.index);
+            assert_eq!(srt.text.trim(), vtt.text.trim(), "clip {}", srt.index);
+        }
+    }
+}
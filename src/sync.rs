@@ -0,0 +1,471 @@
+use crate::SubtitleClip;
+use crate::error::Error;
+use crate::ffmpeg::FFmpegBuilder;
+use crate::timing::LinearTiming;
+
+/// The sample rate audio is decoded at. Speech is still easy to tell from
+/// silence at 8 kHz, and it keeps a whole episode to a few megabytes.
+const SAMPLE_RATE: u32 = 8000;
+/// The length of each step of the voice activity timeline, in seconds.
+pub const FRAME_SECONDS: f64 = 0.01;
+/// How far above the quietest stretches of the audio speech must be, in dB.
+const SPEECH_ABOVE_NOISE: f64 = 12.0;
+/// Audio quieter than this is never speech, however quiet the rest is, in dBFS.
+const MIN_SPEECH_LEVEL: f64 = -55.0;
+/// Pauses shorter than this are part of the speech around them, in frames.
+const MIN_PAUSE_FRAMES: usize = 20;
+
+/// SyncOptions controls how subtitles are aligned to the speech in a video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncOptions {
+    /// The largest shift tried in either direction, in seconds.
+    pub max_offset: f64,
+    /// Lets the shift change between lines, as when a release cuts or adds a
+    /// scene, whenever that aligns at least this many more seconds of speech.
+    /// `None` shifts every line by the same amount.
+    pub split_penalty: Option<f64>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            max_offset: 60.0,
+            split_penalty: None,
+        }
+    }
+}
+
+/// Alignment is the shift found for each line by `align`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+    /// The shift of each line in seconds, in the order the lines were given.
+    pub offsets: Vec<f64>,
+}
+
+impl Alignment {
+    /// The shift of the most lines, which is the shift of every line without
+    /// splits.
+    pub fn offset(&self) -> f64 {
+        let mut offsets = self.offsets.clone();
+        offsets.sort_by(f64::total_cmp);
+        offsets
+            .chunk_by(|a, b| a == b)
+            .max_by_key(|run| run.len())
+            .map_or(0.0, |run| run[0])
+    }
+
+    /// The number of places the shift changes between consecutive lines.
+    pub fn splits(&self) -> usize {
+        self.offsets
+            .windows(2)
+            .filter(|pair| pair[0] != pair[1])
+            .count()
+    }
+
+    /// Shifts `clips`, which must be the lines that were aligned. Lines that
+    /// end up ending at or before the start of the video are dropped.
+    pub fn apply(&self, clips: Vec<SubtitleClip>) -> Result<Vec<SubtitleClip>, Error> {
+        if clips.len() != self.offsets.len() {
+            return Err(Error::InvalidArgument(format!(
+                "{} lines were aligned, but {} were given",
+                self.offsets.len(),
+                clips.len()
+            )));
+        }
+        let mut shifted = Vec::with_capacity(clips.len());
+        for (clip, &offset) in clips.into_iter().zip(&self.offsets) {
+            let timing = LinearTiming { scale: 1.0, offset };
+            let clip = SubtitleClip {
+                start_time: timing.apply(clip.start_time)?,
+                end_time: timing.apply(clip.end_time)?,
+                ..clip
+            };
+            if !clip.end_time.is_zero() {
+                shifted.push(clip);
+            }
+        }
+        Ok(shifted)
+    }
+}
+
+/// Aligns `clips` to the speech in `input`, reading the audio `stream` or the
+/// default audio stream.
+pub async fn synchronize(
+    input: &str,
+    stream: Option<u32>,
+    clips: &[SubtitleClip],
+    options: SyncOptions,
+) -> Result<Alignment, Error> {
+    let samples = decode_audio(input, stream).await?;
+    let speech = voice_activity(&samples, SAMPLE_RATE);
+    Ok(align(&speech, clips, options))
+}
+
+// Decodes the audio of `input` as mono 16-bit samples at `SAMPLE_RATE`.
+async fn decode_audio(input: &str, stream: Option<u32>) -> Result<Vec<i16>, Error> {
+    let stdout = decode_builder(input.to_string(), stream)
+        .build()?
+        .run_for_stdout()
+        .await?;
+    Ok(stdout
+        .chunks_exact(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect())
+}
+
+// Writes the whole audio of `input` to stdout as PCM samples.
+fn decode_builder(input: String, stream: Option<u32>) -> FFmpegBuilder {
+    let mut builder = FFmpegBuilder::new(input, "-".to_string())
+        .quiet()
+        .disable_video()
+        .decode_to_pcm(SAMPLE_RATE);
+    if let Some(stream) = stream {
+        builder = builder.map_audio_stream(stream);
+    }
+    builder
+}
+
+/// Marks each `FRAME_SECONDS` of `samples` as speech or not.
+///
+/// Speech is anything well above the noise floor, which is taken from the
+/// quietest tenth of the audio so that it adapts to each recording. Short
+/// pauses are filled in, since subtitles span the pauses within a line too.
+pub fn voice_activity(samples: &[i16], sample_rate: u32) -> Vec<bool> {
+    let frame_length = ((sample_rate as f64 * FRAME_SECONDS) as usize).max(1);
+    let levels: Vec<f64> = samples
+        .chunks(frame_length)
+        .map(|frame| {
+            let power = frame
+                .iter()
+                .map(|&sample| (sample as f64 / 32768.0).powi(2))
+                .sum::<f64>()
+                / frame.len() as f64;
+            10.0 * (power + 1e-10).log10()
+        })
+        .collect();
+    if levels.is_empty() {
+        return Vec::new();
+    }
+
+    let mut sorted = levels.clone();
+    sorted.sort_by(f64::total_cmp);
+    let noise = sorted[sorted.len() / 10];
+    let threshold = (noise + SPEECH_ABOVE_NOISE).max(MIN_SPEECH_LEVEL);
+    let mut speech: Vec<bool> = levels.iter().map(|&level| level > threshold).collect();
+
+    // Fills pauses between speech that are shorter than `MIN_PAUSE_FRAMES`.
+    let mut last_speech = None;
+    for frame in 0..speech.len() {
+        if !speech[frame] {
+            continue;
+        }
+        if let Some(last) = last_speech
+            && frame - last <= MIN_PAUSE_FRAMES
+        {
+            speech[last..frame].fill(true);
+        }
+        last_speech = Some(frame);
+    }
+    speech
+}
+
+/// Finds the shift of each of `clips` that lines them up best with `speech`,
+/// a timeline from `voice_activity`.
+///
+/// Each frame a line covers scores one if it is speech and loses one if it is
+/// not, so lines are pulled onto speech and out of silence. Without a split
+/// penalty every line gets the shift with the best total. With one, the shifts
+/// are chosen together so that the total less the penalty of each change is
+/// best, like alass does. Ties go to the smallest shift.
+pub fn align(speech: &[bool], clips: &[SubtitleClip], options: SyncOptions) -> Alignment {
+    let max_offset = (options.max_offset.max(0.0) / FRAME_SECONDS).round() as i64;
+    let offsets: Vec<i64> = (-max_offset..=max_offset).collect();
+    let timeline = Timeline::new(speech);
+    let frame = |time: std::time::Duration| (time.as_secs_f64() / FRAME_SECONDS).round() as i64;
+    // Lines are aligned in order of time, so splits fall between neighbours.
+    let mut order: Vec<usize> = (0..clips.len()).collect();
+    order.sort_by_key(|&position| clips[position].start_time);
+    let spans: Vec<(i64, i64)> = order
+        .iter()
+        .map(|&position| {
+            (
+                frame(clips[position].start_time),
+                frame(clips[position].end_time),
+            )
+        })
+        .collect();
+    let score =
+        |(start, end): (i64, i64), offset: i64| timeline.sum(start + offset, end + offset) as f64;
+    // Prefers the smaller shift when two score the same.
+    let better = |a: (f64, i64), b: (f64, i64)| a.0 > b.0 || (a.0 == b.0 && a.1.abs() < b.1.abs());
+
+    let chosen: Vec<i64> = match options.split_penalty {
+        None => {
+            let mut best = (f64::NEG_INFINITY, 0);
+            for &offset in &offsets {
+                let total = spans.iter().map(|&span| score(span, offset)).sum();
+                if better((total, offset), best) {
+                    best = (total, offset);
+                }
+            }
+            vec![best.1; spans.len()]
+        }
+        Some(penalty) => {
+            let penalty = penalty.max(0.0) / FRAME_SECONDS;
+            align_with_splits(&spans, &offsets, penalty, score, better)
+        }
+    };
+
+    let mut aligned = vec![0.0; clips.len()];
+    for (&position, offset) in order.iter().zip(chosen) {
+        aligned[position] = offset as f64 * FRAME_SECONDS;
+    }
+    Alignment { offsets: aligned }
+}
+
+// Chooses a shift per span by dynamic programming, where `best[o]` is the
+// best total of the spans so far with the latest one shifted by `offsets[o]`.
+fn align_with_splits(
+    spans: &[(i64, i64)],
+    offsets: &[i64],
+    penalty: f64,
+    score: impl Fn((i64, i64), i64) -> f64,
+    better: impl Fn((f64, i64), (f64, i64)) -> bool,
+) -> Vec<i64> {
+    if spans.is_empty() {
+        return Vec::new();
+    }
+    let mut best: Vec<f64> = offsets
+        .iter()
+        .map(|&offset| score(spans[0], offset))
+        .collect();
+    // Whether each span kept the shift of the previous one, and otherwise
+    // which shift the previous span had.
+    let mut kept: Vec<Vec<bool>> = vec![vec![true; offsets.len()]];
+    let mut switched_from = vec![0];
+    for &span in &spans[1..] {
+        let top = argmax(&best, offsets, &better);
+        let switch = best[top] - penalty;
+        let mut keeps = Vec::with_capacity(offsets.len());
+        for (o, &offset) in offsets.iter().enumerate() {
+            let keep = best[o] >= switch;
+            best[o] = best[o].max(switch) + score(span, offset);
+            keeps.push(keep);
+        }
+        kept.push(keeps);
+        switched_from.push(top);
+    }
+
+    let mut o = argmax(&best, offsets, &better);
+    let mut chosen = vec![0; spans.len()];
+    for span in (0..spans.len()).rev() {
+        chosen[span] = offsets[o];
+        if !kept[span][o] {
+            o = switched_from[span];
+        }
+    }
+    chosen
+}
+
+fn argmax(
+    totals: &[f64],
+    offsets: &[i64],
+    better: impl Fn((f64, i64), (f64, i64)) -> bool,
+) -> usize {
+    (1..totals.len()).fold(0, |top, o| {
+        if better((totals[o], offsets[o]), (totals[top], offsets[top])) {
+            o
+        } else {
+            top
+        }
+    })
+}
+
+// Timeline sums the scores of a range of frames in constant time. Frames
+// outside the audio count as silence.
+struct Timeline {
+    // `prefix[i]` is the total score of the frames before `i`.
+    prefix: Vec<i64>,
+}
+
+impl Timeline {
+    fn new(speech: &[bool]) -> Self {
+        let mut prefix = Vec::with_capacity(speech.len() + 1);
+        prefix.push(0);
+        for &speech in speech {
+            prefix.push(prefix.last().unwrap() + if speech { 1 } else { -1 });
+        }
+        Timeline { prefix }
+    }
+
+    // The total score of the frames from `start` up to `end`.
+    fn sum(&self, start: i64, end: i64) -> i64 {
+        self.at(end) - self.at(start)
+    }
+
+    fn at(&self, frame: i64) -> i64 {
+        let length = self.prefix.len() as i64 - 1;
+        if frame < 0 {
+            -frame
+        } else if frame > length {
+            self.prefix[length as usize] - (frame - length)
+        } else {
+            self.prefix[frame as usize]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const TEST_VIDEO: &str = "videos/Minecraft_1.20生存#1.偏頭.mkv";
+    const TEST_SRT: &str = "videos/Minecraft_1.20生存#1.偏頭.zh.srt";
+    const RATE: u32 = 8000;
+
+    // Synthesizes audio with a 220 Hz tone during each of `tones` and faint
+    // noise elsewhere, standing in for speech and silence.
+    fn tones(length: f64, tones: &[(f64, f64)]) -> Vec<i16> {
+        let mut noise: u32 = 1;
+        (0..(length * RATE as f64) as usize)
+            .map(|sample| {
+                let time = sample as f64 / RATE as f64;
+                noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let hiss = (noise >> 16) as f64 / 65536.0 * 60.0 - 30.0;
+                let tone = tones
+                    .iter()
+                    .any(|&(start, end)| start <= time && time < end);
+                let signal = if tone {
+                    (time * 220.0 * std::f64::consts::TAU).sin() * 8000.0
+                } else {
+                    0.0
+                };
+                (signal + hiss) as i16
+            })
+            .collect()
+    }
+
+    fn clip(index: usize, start: f64, end: f64) -> SubtitleClip {
+        SubtitleClip {
+            index,
+            start_time: Duration::from_secs_f64(start),
+            end_time: Duration::from_secs_f64(end),
+            ..Default::default()
+        }
+    }
+
+    // Lines of irregular lengths and gaps, so that no other shift fits.
+    const LINES: [(f64, f64); 6] = [
+        (2.0, 3.5),
+        (4.1, 6.0),
+        (9.0, 9.8),
+        (12.5, 15.0),
+        (16.0, 17.2),
+        (21.0, 23.5),
+    ];
+
+    #[test]
+    fn test_decode_builder() {
+        let args = decode_builder("in.mkv".to_string(), Some(2))
+            .build()
+            .unwrap()
+            .args();
+        assert_eq!(
+            args.join(" "),
+            "-hide_banner -nostats -i in.mkv -map 0:a:2 -vn -ac 1 -ar 8000 -f s16le -"
+        );
+        assert!(decode_builder(String::new(), None).build().is_err());
+    }
+
+    #[test]
+    fn test_voice_activity() {
+        let speech = voice_activity(&tones(3.0, &[(1.0, 2.0)]), RATE);
+        assert_eq!(speech.len(), 300);
+        assert!(!speech[50] && speech[150] && !speech[250]);
+        // Pauses too short to be between lines are filled in.
+        let speech = voice_activity(&tones(3.0, &[(1.0, 1.5), (1.6, 2.0)]), RATE);
+        assert!(speech[155]);
+        assert!(voice_activity(&[], RATE).is_empty());
+    }
+
+    // The lines shifted by `shift(index)` seconds.
+    fn shifted(shift: impl Fn(usize) -> f64) -> Vec<SubtitleClip> {
+        LINES
+            .iter()
+            .enumerate()
+            .map(|(index, &(start, end))| clip(index + 1, start + shift(index), end + shift(index)))
+            .collect()
+    }
+
+    #[test]
+    fn test_align_offset() {
+        // The subtitles are 2.5s late.
+        let speech = voice_activity(&tones(30.0, &LINES), RATE);
+        let clips = shifted(|_| 2.5);
+        let alignment = align(&speech, &clips, SyncOptions::default());
+        assert!((alignment.offset() + 2.5).abs() < 0.02, "{:?}", alignment);
+        assert_eq!(alignment.splits(), 0);
+
+        let aligned = alignment.apply(clips).unwrap();
+        assert!((aligned[3].start_time.as_secs_f64() - 12.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_align_with_splits() {
+        // A scene was cut after the fourth line, so the lines before it are 1s
+        // late and the rest 1s early.
+        let speech = voice_activity(&tones(30.0, &LINES), RATE);
+        let clips = shifted(|index| if index < 4 { 1.0 } else { -1.0 });
+
+        let options = SyncOptions {
+            split_penalty: Some(0.5),
+            ..Default::default()
+        };
+        let alignment = align(&speech, &clips, options);
+        assert_eq!(alignment.splits(), 1, "{:?}", alignment);
+        for (index, offset) in alignment.offsets.iter().enumerate() {
+            let expected = if index < 4 { -1.0 } else { 1.0 };
+            assert!((offset - expected).abs() < 0.02, "{:?}", alignment);
+        }
+
+        // Without splits, one shift has to do for every line.
+        let alignment = align(&speech, &clips, SyncOptions::default());
+        assert_eq!(alignment.splits(), 0);
+    }
+
+    #[test]
+    fn test_align_without_speech() {
+        let clips = [clip(1, 1.0, 2.0)];
+        let alignment = align(&vec![false; 500], &clips, SyncOptions::default());
+        assert_eq!(alignment.offsets, [0.0]);
+        assert!(align(&[], &[], SyncOptions::default()).offsets.is_empty());
+        assert!(matches!(
+            alignment.apply(Vec::new()),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_synchronize_sample() {
+        let clips = crate::subtitles::from_file(TEST_SRT).expect("failed to parse SRT file");
+        // Delaying the matching subtitles should be undone.
+        let delayed: Vec<SubtitleClip> = clips
+            .iter()
+            .map(|clip| SubtitleClip {
+                start_time: clip.start_time + Duration::from_millis(1500),
+                end_time: clip.end_time + Duration::from_millis(1500),
+                ..clip.clone()
+            })
+            .collect();
+
+        let alignment = synchronize(TEST_VIDEO, None, &delayed, SyncOptions::default())
+            .await
+            .expect("failed to synchronize");
+        assert!(
+            (alignment.offset() + 1.5).abs() < 0.3,
+            "found {}",
+            alignment.offset()
+        );
+    }
+}
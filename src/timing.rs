@@ -0,0 +1,489 @@
+use crate::SubtitleClip;
+use crate::error::Error;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// TimingOptions corrects the times of subtitles made for a different release
+/// of a video. Frame rate conversion is applied first, then the stretch, then
+/// the offset.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimingOptions {
+    /// Seconds added to every time, which may be negative.
+    pub offset: f64,
+    /// Moves two lines to known times, stretching the others between them.
+    pub stretch: Option<Stretch>,
+    pub frame_rate: Option<FrameRateConversion>,
+}
+
+/// StretchPoint is a line of a subtitle file along with the time in the video
+/// where it should start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StretchPoint {
+    /// The `index` of the line, as numbered in the subtitle file.
+    pub line: usize,
+    /// The time in seconds.
+    pub time: f64,
+}
+
+/// Stretch maps the start of one line to one time and the start of another
+/// to another, scaling everything linearly to match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stretch {
+    pub first: StretchPoint,
+    pub second: StretchPoint,
+}
+
+/// FrameRateConversion converts subtitles timed for a video at `from` frames
+/// per second, such as a 25 fps PAL release, to the same video at `to`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRateConversion {
+    pub from: f64,
+    pub to: f64,
+}
+
+/// The NTSC film rate, which is usually written as 23.976.
+const NTSC_FILM: f64 = 24000.0 / 1001.0;
+
+/// Conversions between the frame rates releases commonly differ by.
+pub const COMMON_FRAME_RATE_CONVERSIONS: [FrameRateConversion; 4] = [
+    FrameRateConversion {
+        from: 25.0,
+        to: NTSC_FILM,
+    },
+    FrameRateConversion {
+        from: NTSC_FILM,
+        to: 25.0,
+    },
+    FrameRateConversion {
+        from: 24.0,
+        to: NTSC_FILM,
+    },
+    FrameRateConversion {
+        from: NTSC_FILM,
+        to: 24.0,
+    },
+];
+
+impl std::fmt::Display for FrameRateConversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Trims 25.000 to 25 but keeps 23.976.
+        let rate = |rate: f64| {
+            let rate = format!("{:.3}", rate);
+            rate.trim_end_matches('0').trim_end_matches('.').to_string()
+        };
+        write!(f, "{} → {} fps", rate(self.from), rate(self.to))
+    }
+}
+
+/// LinearTiming maps each time `t` to `t * scale + offset`, and is what every
+/// combination of `TimingOptions` comes down to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearTiming {
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl LinearTiming {
+    /// Maps `time`, stopping at 0 since a clip cannot start before the video.
+    /// Fails if the time is too large to represent.
+    pub fn apply(&self, time: Duration) -> Result<Duration, Error> {
+        let seconds = (time.as_secs_f64() * self.scale + self.offset).max(0.0);
+        Duration::try_from_secs_f64(seconds)
+            .map_err(|_| Error::InvalidArgument(format!("time {}s is out of range", seconds)))
+    }
+}
+
+impl TimingOptions {
+    /// Whether the options leave every time as it is.
+    pub fn is_identity(&self) -> bool {
+        *self == TimingOptions::default()
+    }
+
+    /// Works out the linear map for `clips`, in which the stretch lines are
+    /// looked up.
+    pub fn timing(&self, clips: &[SubtitleClip]) -> Result<LinearTiming, Error> {
+        let mut scale = 1.0;
+        let mut offset = 0.0;
+        if let Some(FrameRateConversion { from, to }) = self.frame_rate {
+            if !(from > 0.0 && to > 0.0) {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid frame rates {} and {}",
+                    from, to
+                )));
+            }
+            // A video sped up from `to` to `from` fps plays each frame sooner.
+            scale = from / to;
+        }
+        if let Some(Stretch { first, second }) = self.stretch {
+            let start = |point: StretchPoint| {
+                clips
+                    .iter()
+                    .find(|clip| clip.index == point.line)
+                    .map(|clip| clip.start_time.as_secs_f64() * scale)
+                    .ok_or_else(|| {
+                        Error::InvalidArgument(format!("there is no line {}", point.line))
+                    })
+            };
+            let (a, b) = (start(first)?, start(second)?);
+            if a == b {
+                return Err(Error::InvalidArgument(format!(
+                    "lines {} and {} start at the same time, so cannot be stretched apart",
+                    first.line, second.line
+                )));
+            }
+            let stretch = (second.time - first.time) / (b - a);
+            if stretch <= 0.0 {
+                return Err(Error::InvalidArgument(format!(
+                    "lines {} and {} would swap places",
+                    first.line, second.line
+                )));
+            }
+            scale *= stretch;
+            offset = first.time - a * stretch;
+        }
+        Ok(LinearTiming {
+            scale,
+            offset: offset + self.offset,
+        })
+    }
+
+    /// Corrects the times of `clips`. Lines that end up ending at or before
+    /// the start of the video are dropped.
+    pub fn apply(&self, clips: Vec<SubtitleClip>) -> Result<Vec<SubtitleClip>, Error> {
+        if self.is_identity() {
+            return Ok(clips);
+        }
+        let timing = self.timing(&clips)?;
+        let mut corrected = Vec::with_capacity(clips.len());
+        for clip in clips {
+            let clip = SubtitleClip {
+                start_time: timing.apply(clip.start_time)?,
+                end_time: timing.apply(clip.end_time)?,
+                ..clip
+            };
+            if !clip.end_time.is_zero() {
+                corrected.push(clip);
+            }
+        }
+        Ok(corrected)
+    }
+
+    /// Loads the options saved for the subtitle file at `subtitle_path`, or
+    /// the defaults if none were saved.
+    pub fn load(subtitle_path: &str) -> Result<Self, Error> {
+        let path = sidecar_path(subtitle_path);
+        if !path.exists() {
+            return Ok(TimingOptions::default());
+        }
+        let json = serde_json::from_slice(&std::fs::read(&path)?).map_err(|error| {
+            Error::InvalidOutput(format!("{} is not valid JSON: {}", path.display(), error))
+        })?;
+        Ok(from_json(&json))
+    }
+
+    /// Saves the options for the subtitle file at `subtitle_path`, next to
+    /// it. Saving the defaults removes any saved options.
+    pub fn save(&self, subtitle_path: &str) -> Result<(), Error> {
+        let path = sidecar_path(subtitle_path);
+        if self.is_identity() {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        let json = serde_json::to_vec_pretty(&to_json(self)).expect("JSON values always serialize");
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+// Timing options are saved beside the subtitle, as `Show.ja.srt.timing.json`.
+fn sidecar_path(subtitle_path: &str) -> PathBuf {
+    let mut path = Path::new(subtitle_path).as_os_str().to_owned();
+    path.push(".timing.json");
+    PathBuf::from(path)
+}
+
+fn to_json(options: &TimingOptions) -> Value {
+    let point = |point: StretchPoint| json!({ "line": point.line, "time": point.time });
+    json!({
+        "offset": options.offset,
+        "stretch": options.stretch.map(|stretch| json!({
+            "first": point(stretch.first),
+            "second": point(stretch.second),
+        })),
+        "frame_rate": options.frame_rate.map(|rate| json!({ "from": rate.from, "to": rate.to })),
+    })
+}
+
+// Reads options written by `to_json`, ignoring anything missing or invalid.
+fn from_json(json: &Value) -> TimingOptions {
+    let point = |point: &Value| {
+        Some(StretchPoint {
+            line: point["line"].as_u64()? as usize,
+            time: point["time"].as_f64()?,
+        })
+    };
+    TimingOptions {
+        offset: json["offset"].as_f64().unwrap_or(0.0),
+        stretch: (|| {
+            Some(Stretch {
+                first: point(&json["stretch"]["first"])?,
+                second: point(&json["stretch"]["second"])?,
+            })
+        })(),
+        frame_rate: (|| {
+            Some(FrameRateConversion {
+                from: json["frame_rate"]["from"].as_f64()?,
+                to: json["frame_rate"]["to"].as_f64()?,
+            })
+        })(),
+    }
+}
+
+/// Parses a time in seconds, written as `SS.mmm`, `MM:SS.mmm` or
+/// `HH:MM:SS.mmm`. A comma may be used for the decimal point, as in SRT files.
+pub fn parse_time(time: &str) -> Result<f64, String> {
+    let invalid = || format!("invalid time {:?}, expected HH:MM:SS.mmm", time);
+    let (negative, unsigned) = match time.trim().strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, time.trim()),
+    };
+    let mut seconds = 0.0;
+    let fields: Vec<&str> = unsigned.split(':').collect();
+    if fields.len() > 3 {
+        return Err(invalid());
+    }
+    for field in fields {
+        let value: f64 = field.replace(',', ".").parse().map_err(|_| invalid())?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(if negative { -seconds } else { seconds })
+}
+
+/// Formats `seconds` as `HH:MM:SS.mmm`, the way `parse_time` reads it.
+pub fn format_time(seconds: f64) -> String {
+    let sign = if seconds < 0.0 { "-" } else { "" };
+    let millis = (seconds.abs() * 1000.0).round() as u64;
+    format!(
+        "{}{:02}:{:02}:{:02}.{:03}",
+        sign,
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+impl FromStr for FrameRateConversion {
+    type Err = String;
+
+    /// Parses `FROM:TO`, as in `25:23.976`.
+    fn from_str(rates: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid frame rates {:?}, expected FROM:TO", rates);
+        let (from, to) = rates.split_once(':').ok_or_else(invalid)?;
+        let rate = |rate: &str| {
+            rate.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| *rate > 0.0 && rate.is_finite())
+                .ok_or_else(invalid)
+        };
+        Ok(FrameRateConversion {
+            from: rate(from)?,
+            to: rate(to)?,
+        })
+    }
+}
+
+impl FromStr for Stretch {
+    type Err = String;
+
+    /// Parses `LINE=TIME,LINE=TIME`, as in `12=00:01:05.300,480=00:22:10.000`.
+    fn from_str(stretch: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid stretch {:?}, expected LINE=TIME,LINE=TIME",
+                stretch
+            )
+        };
+        let point = |point: &str| -> Result<StretchPoint, String> {
+            let (line, time) = point.split_once('=').ok_or_else(invalid)?;
+            Ok(StretchPoint {
+                line: line.trim().parse().map_err(|_| invalid())?,
+                time: parse_time(time)?,
+            })
+        };
+        let (first, second) = stretch.split_once(',').ok_or_else(invalid)?;
+        Ok(Stretch {
+            first: point(first)?,
+            second: point(second)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    const TEST_OUTPUT_DIR_BASE: &str = "/tmp/subs2srs_tests";
+
+    // Helper to create a unique test directory
+    fn setup_test_dir() -> PathBuf {
+        let test_run_id = Uuid::new_v4().to_string();
+        let output_dir = PathBuf::from(TEST_OUTPUT_DIR_BASE).join(test_run_id);
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir).unwrap();
+        }
+        fs::create_dir_all(&output_dir).unwrap();
+        output_dir
+    }
+
+    fn clip(index: usize, start: f64, end: f64) -> SubtitleClip {
+        SubtitleClip {
+            index,
+            start_time: Duration::from_secs_f64(start),
+            end_time: Duration::from_secs_f64(end),
+            ..Default::default()
+        }
+    }
+
+    fn times(clips: &[SubtitleClip]) -> Vec<(f64, f64)> {
+        clips
+            .iter()
+            .map(|clip| {
+                let round = |time: Duration| (time.as_secs_f64() * 1000.0).round() / 1000.0;
+                (round(clip.start_time), round(clip.end_time))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_offset() {
+        let options = TimingOptions {
+            offset: -1.5,
+            ..Default::default()
+        };
+        let clips = options
+            .apply(vec![
+                clip(1, 1.0, 1.4),
+                clip(2, 1.0, 2.0),
+                clip(3, 10.0, 12.0),
+            ])
+            .unwrap();
+        // The first line would end before the video starts.
+        assert_eq!(times(&clips), [(0.0, 0.5), (8.5, 10.5)]);
+        assert_eq!(clips[0].index, 2);
+
+        // A shift too large to represent is an error rather than a panic.
+        let options = TimingOptions {
+            offset: 1e20,
+            ..Default::default()
+        };
+        assert!(matches!(
+            options.apply(vec![clip(1, 1.0, 2.0)]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_frame_rate_conversion() {
+        let options = TimingOptions {
+            frame_rate: Some("25:24".parse().unwrap()),
+            ..Default::default()
+        };
+        let clips = options.apply(vec![clip(1, 24.0, 48.0)]).unwrap();
+        assert_eq!(times(&clips), [(25.0, 50.0)]);
+    }
+
+    #[test]
+    fn test_stretch() {
+        let clips = vec![
+            clip(1, 10.0, 11.0),
+            clip(2, 20.0, 21.0),
+            clip(3, 30.0, 31.0),
+        ];
+        let options = TimingOptions {
+            stretch: Some("1=12,3=52".parse().unwrap()),
+            offset: 1.0,
+            ..Default::default()
+        };
+        let timing = options.timing(&clips).unwrap();
+        assert_eq!(
+            timing,
+            LinearTiming {
+                scale: 2.0,
+                offset: -7.0
+            }
+        );
+        let clips = options.apply(clips).unwrap();
+        assert_eq!(times(&clips), [(13.0, 15.0), (33.0, 35.0), (53.0, 55.0)]);
+
+        let invalid = |stretch: &str| {
+            TimingOptions {
+                stretch: Some(stretch.parse().unwrap()),
+                ..Default::default()
+            }
+            .timing(&[clip(1, 10.0, 11.0), clip(2, 20.0, 21.0)])
+        };
+        assert!(invalid("1=5,9=10").is_err(), "line 9 does not exist");
+        assert!(invalid("1=5,1=10").is_err(), "the lines are the same");
+        assert!(invalid("1=10,2=5").is_err(), "the lines would swap");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_time("1:02:03,5"), Ok(3723.5));
+        assert_eq!(parse_time("-2.25"), Ok(-2.25));
+        assert!(parse_time("1:2:3:4").is_err());
+        assert_eq!(format_time(0.0), "00:00:00.000");
+        assert_eq!(format_time(3723.5), "01:02:03.500");
+        assert_eq!(format_time(3723.045), "01:02:03.045");
+        assert_eq!(parse_time(&format_time(-61.25)), Ok(-61.25));
+        assert!("25".parse::<FrameRateConversion>().is_err());
+        assert!("25:0".parse::<FrameRateConversion>().is_err());
+        assert_eq!(
+            "4=1:00,9=2:00.5".parse(),
+            Ok(Stretch {
+                first: StretchPoint {
+                    line: 4,
+                    time: 60.0
+                },
+                second: StretchPoint {
+                    line: 9,
+                    time: 120.5
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = setup_test_dir();
+        let subtitle = dir.join("Show.ja.srt").display().to_string();
+
+        assert_eq!(
+            TimingOptions::load(&subtitle).unwrap(),
+            TimingOptions::default()
+        );
+        let options = TimingOptions {
+            offset: 0.5,
+            stretch: Some("1=12,3=52".parse().unwrap()),
+            frame_rate: Some("25:23.976".parse().unwrap()),
+        };
+        options.save(&subtitle).unwrap();
+        assert!(dir.join("Show.ja.srt.timing.json").exists());
+        assert_eq!(TimingOptions::load(&subtitle).unwrap(), options);
+
+        TimingOptions::default().save(&subtitle).unwrap();
+        assert!(!dir.join("Show.ja.srt.timing.json").exists());
+    }
+}
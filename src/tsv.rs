@@ -0,0 +1,110 @@
+use crate::GeneratedClip;
+use crate::note::{self, NoteFields};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Writes an Anki import file to `output` with one tab-separated row per clip.
+///
+/// The file starts with Anki's `#` header lines so that the separator, HTML
+/// handling, columns, deck and tags are all picked up automatically on import.
+/// The media files referenced by each row must be copied into Anki's media
+/// folder separately.
+pub(crate) fn export_tsv(
+    deck_name: &str,
+    tags: &[String],
+    clips: &[GeneratedClip],
+    output: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    assert!(!deck_name.is_empty());
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    writeln!(writer, "#separator:tab")?;
+    writeln!(writer, "#html:true")?;
+    writeln!(writer, "#columns:{}", note::FIELD_NAMES.join("\t"))?;
+    writeln!(writer, "#deck:{}", deck_name)?;
+    writeln!(writer, "#tags:{}", tags.join(" "))?;
+
+    for generated in clips {
+        let fields = NoteFields::from_clip(generated)?;
+        let row: Vec<String> = fields
+            .values()
+            .iter()
+            .map(|v| v.replace('\t', " "))
+            .collect();
+        writeln!(writer, "{}", row.join("\t"))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Turns `name` into a valid Anki tag, which may not contain whitespace.
+pub(crate) fn tag_for(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SubtitleClip;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const TEST_OUTPUT_DIR_BASE: &str = "/tmp/subs2srs_tests";
+
+    // Helper to create a unique test directory
+    fn setup_test_dir() -> PathBuf {
+        let test_run_id = Uuid::new_v4().to_string();
+        let output_dir = PathBuf::from(TEST_OUTPUT_DIR_BASE).join(test_run_id);
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir).unwrap();
+        }
+        fs::create_dir_all(&output_dir).unwrap();
+        output_dir
+    }
+
+    fn generated_clip(index: usize, text: &str, meaning: &str) -> GeneratedClip {
+        GeneratedClip {
+            clip: SubtitleClip {
+                index,
+                start_time: Duration::from_secs(index as u64),
+                end_time: Duration::from_secs(index as u64 + 1),
+                text: text.to_string(),
+                meaning: meaning.to_string(),
+                ..Default::default()
+            },
+            screenshot_path: PathBuf::from(format!("/out/screenshot_{}.png", index)),
+            audio_path: PathBuf::from(format!("/out/audio_clip_{}.mp3", index)),
+            video_path: None,
+        }
+    }
+
+    #[test]
+    fn test_export_tsv() {
+        let output = setup_test_dir().join("deck.tsv");
+        let clips = vec![
+            generated_clip(1, "你好", "Hello"),
+            generated_clip(2, "a\tb\nc", ""),
+        ];
+        let tags = vec!["subs2srs".to_string(), tag_for("Minecraft 1.20")];
+
+        export_tsv("Minecraft", &tags, &clips, &output).expect("failed to export tsv");
+
+        let contents = fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            contents.lines().collect::<Vec<_>>(),
+            [
+                "#separator:tab",
+                "#html:true",
+                "#columns:Sentence\tMeaning\tAudio\tImage\tVideo",
+                "#deck:Minecraft",
+                "#tags:subs2srs Minecraft_1.20",
+                "你好\tHello\t[sound:audio_clip_1.mp3]\t<img src=\"screenshot_1.png\">\t",
+                "a b<br>c\t\t[sound:audio_clip_2.mp3]\t<img src=\"screenshot_2.png\">\t",
+            ]
+        );
+    }
+}
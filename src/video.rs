@@ -0,0 +1,225 @@
+use crate::audio::{AudioCodec, AudioEncoding};
+use crate::error::Error;
+use crate::ffmpeg::FFmpegBuilder;
+use crate::screenshot::{Crop, ImageSize};
+use std::str::FromStr;
+
+/// VideoFormat is the format video clips are saved in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// VP9 with Opus audio.
+    #[default]
+    Webm,
+    /// H.264 with AAC audio, which plays almost everywhere.
+    Mp4,
+    /// A silent animation shown like an image.
+    AnimatedWebp,
+    /// A silent animation shown like an image, larger than WebP.
+    Gif,
+}
+
+impl VideoFormat {
+    pub const ALL: [VideoFormat; 4] = [
+        VideoFormat::Webm,
+        VideoFormat::Mp4,
+        VideoFormat::AnimatedWebp,
+        VideoFormat::Gif,
+    ];
+
+    /// The extension of the files written in this format, without a dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Webm => "webm",
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::AnimatedWebp => "webp",
+            VideoFormat::Gif => "gif",
+        }
+    }
+
+    /// Whether the format is a silent animation, which Anki shows with an
+    /// `<img>` tag rather than playing with `[sound:]`.
+    pub fn is_animation(self) -> bool {
+        matches!(self, VideoFormat::AnimatedWebp | VideoFormat::Gif)
+    }
+
+    /// The highest constant rate factor of the encoder. Animations have none.
+    pub fn max_crf(self) -> Option<u8> {
+        match self {
+            VideoFormat::Webm => Some(63),
+            VideoFormat::Mp4 => Some(51),
+            VideoFormat::AnimatedWebp | VideoFormat::Gif => None,
+        }
+    }
+}
+
+impl std::fmt::Display for VideoFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VideoFormat::Webm => "webm",
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::AnimatedWebp => "webp",
+            VideoFormat::Gif => "gif",
+        })
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        VideoFormat::ALL
+            .into_iter()
+            .find(|format| format.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                format!(
+                    "unknown video format {:?}, expected webm, mp4, webp or gif",
+                    name
+                )
+            })
+    }
+}
+
+/// VideoOptions controls the video clip made for each line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoOptions {
+    pub format: VideoFormat,
+    /// The constant rate factor of WebM and MP4, where lower is better.
+    pub crf: u8,
+    pub size: ImageSize,
+    /// Drops frames down to this rate, which keeps animations small.
+    pub frame_rate: Option<u32>,
+    /// Keeps the audio of WebM and MP4 clips. Animations are always silent.
+    pub audio: bool,
+}
+
+impl VideoOptions {
+    /// Whether the clips keep their audio, which animations never do.
+    pub fn has_audio(&self) -> bool {
+        self.audio && !self.format.is_animation()
+    }
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            format: VideoFormat::Webm,
+            crf: 36,
+            size: ImageSize::Height(360),
+            frame_rate: None,
+            audio: true,
+        }
+    }
+}
+
+/// Records the input from `start_time` to `end_time` as `output_stem` with the
+/// extension of the format added, cropped to `crop` if given, and returns the
+/// path written. Clips with audio use `audio_stream` when given.
+pub async fn record_video_clip(
+    start_time: f64,
+    end_time: f64,
+    input: String,
+    output_stem: String,
+    options: VideoOptions,
+    crop: Option<Crop>,
+    audio_stream: Option<u32>,
+) -> Result<String, Error> {
+    if end_time <= start_time {
+        return Err(Error::InvalidTimeRange {
+            start: start_time,
+            end: end_time,
+        });
+    }
+
+    // The stem may contain dots, so the extension is appended rather than set.
+    let output = format!("{}.{}", output_stem, options.format.extension());
+    let mut builder = video_clip_builder(input, output.clone(), options, crop)
+        .seek_to(start_time)
+        .end_at(end_time);
+    if let Some(stream) = audio_stream
+        && options.has_audio()
+    {
+        builder = builder.map_audio_stream(stream);
+    }
+    builder.build()?.run().await?;
+    Ok(output)
+}
+
+// Sets up everything about a video clip but which part of the input it covers.
+pub(crate) fn video_clip_builder(
+    input: String,
+    output: String,
+    options: VideoOptions,
+    crop: Option<Crop>,
+) -> FFmpegBuilder {
+    let mut builder = FFmpegBuilder::new(input, output)
+        .overwrite()
+        .encode_video(options.format, options.crf);
+    if let Some(crop) = crop {
+        builder = builder.crop(crop);
+    }
+    if let Some(frame_rate) = options.frame_rate {
+        builder = builder.frame_rate(frame_rate);
+    }
+    if options.size != ImageSize::Original {
+        builder = builder.scale_to(options.size);
+    }
+    match options.format {
+        VideoFormat::Webm if options.audio => builder.encode_audio(AudioEncoding {
+            mono: true,
+            ..AudioEncoding::new(AudioCodec::Opus)
+        }),
+        VideoFormat::Mp4 if options.audio => {
+            builder.encode_audio(AudioEncoding::new(AudioCodec::Aac))
+        }
+        _ => builder.disable_audio(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_clip_builder_overwrites() {
+        let args = video_clip_builder(
+            "in.mkv".to_string(),
+            "out.webm".to_string(),
+            VideoOptions::default(),
+            None,
+        )
+        .build()
+        .unwrap()
+        .args();
+        assert!(args.iter().any(|arg| arg == "-y"), "{:?}", args);
+    }
+
+    #[test]
+    fn test_video_format_from_str() {
+        for format in VideoFormat::ALL {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert_eq!("MP4".parse(), Ok(VideoFormat::Mp4));
+        assert!("avi".parse::<VideoFormat>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record_video_clip_invalid_time_range() {
+        let result = record_video_clip(
+            12.0,
+            10.0,
+            "in.mkv".into(),
+            "/tmp/clip".into(),
+            VideoOptions::default(),
+            None,
+            None,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::InvalidTimeRange {
+                start: 12.0,
+                end: 10.0
+            })
+        ));
+    }
+}
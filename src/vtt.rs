@@ -0,0 +1,213 @@
+use crate::SubtitleClip;
+use std::time::Duration;
+
+/// Parses the contents of a WebVTT file into clips.
+///
+/// `NOTE`, `STYLE` and `REGION` blocks are skipped, cue identifiers and cue
+/// settings are ignored, and markup such as `<c>`, `<v>` and `<i>` is removed
+/// from the cue text. Clips are numbered from 1 in the order they appear.
+pub(crate) fn parse(contents: &str) -> Result<Vec<SubtitleClip>, Box<dyn std::error::Error>> {
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    let lines: Vec<(usize, &str)> = contents
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .enumerate()
+        .map(|(number, line)| (number + 1, line))
+        .collect();
+
+    let mut blocks = lines.split(|(_, line)| line.trim().is_empty());
+    let header = blocks.find(|block| !block.is_empty()).unwrap_or(&[]);
+    match header.first() {
+        Some((_, line)) if is_header(line) => {}
+        _ => return Err("missing WEBVTT header".into()),
+    }
+
+    let mut clips = Vec::new();
+    for block in blocks.filter(|block| !block.is_empty()) {
+        let (_, first) = block[0];
+        if ["NOTE", "STYLE", "REGION"]
+            .iter()
+            .any(|keyword| is_keyword_line(first, keyword))
+        {
+            continue;
+        }
+
+        // The timing line is either the first line, or the second if the cue
+        // has an identifier.
+        let timing_position = match block.iter().position(|(_, line)| line.contains("-->")) {
+            Some(position) if position <= 1 => position,
+            _ => {
+                return Err(format!("line {}: expected a cue timing line", block[0].0).into());
+            }
+        };
+        let (number, timing) = block[timing_position];
+        let (start_time, end_time) =
+            parse_timing(timing).map_err(|error| format!("line {}: {}", number, error))?;
+
+        let text = block[timing_position + 1..]
+            .iter()
+            .map(|(_, line)| strip_markup(line))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        clips.push(SubtitleClip {
+            index: clips.len() + 1,
+            start_time,
+            end_time,
+            text,
+            ..Default::default()
+        });
+    }
+
+    Ok(clips)
+}
+
+fn is_header(line: &str) -> bool {
+    is_keyword_line(line, "WEBVTT")
+}
+
+// Whether `line` is `keyword` on its own or followed by whitespace.
+fn is_keyword_line(line: &str, keyword: &str) -> bool {
+    match line.strip_prefix(keyword) {
+        Some(rest) => rest.is_empty() || rest.starts_with([' ', '\t']),
+        None => false,
+    }
+}
+
+// Parses `00:01.000 --> 00:02.000 align:start` into its start and end times.
+fn parse_timing(line: &str) -> Result<(Duration, Duration), String> {
+    let (start, rest) = line
+        .split_once("-->")
+        .ok_or_else(|| format!("invalid cue timing {:?}", line))?;
+    let end = rest.split_whitespace().next().unwrap_or("");
+    Ok((parse_timestamp(start.trim())?, parse_timestamp(end)?))
+}
+
+// Parses `hh:mm:ss.ttt` or `mm:ss.ttt`.
+fn parse_timestamp(timestamp: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid timestamp {:?}", timestamp);
+
+    let (clock, millis) = timestamp.split_once('.').ok_or_else(invalid)?;
+    if millis.len() != 3 {
+        return Err(invalid());
+    }
+    let millis: u64 = millis.parse().map_err(|_| invalid())?;
+
+    let parts = clock
+        .split(':')
+        .map(|part| part.parse::<u64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        [minutes, seconds] => (0, minutes, seconds),
+        _ => return Err(invalid()),
+    };
+    if minutes > 59 || seconds > 59 {
+        return Err(invalid());
+    }
+
+    Ok(Duration::from_millis(
+        ((hours * 60 + minutes) * 60 + seconds) * 1000 + millis,
+    ))
+}
+
+// Removes tags such as `<v Speaker>` and `<c.yellow>` and decodes entities.
+// Ruby annotations (`<rt>...</rt>`) are dropped along with their tags.
+fn strip_markup(line: &str) -> String {
+    let mut text = String::new();
+    let mut in_ruby_text = false;
+    let mut rest = line;
+
+    while let Some(open) = rest.find('<') {
+        if !in_ruby_text {
+            text.push_str(&rest[..open]);
+        }
+        let Some(close) = rest[open..].find('>') else {
+            rest = &rest[open..];
+            break;
+        };
+        match &rest[open + 1..open + close] {
+            "rt" => in_ruby_text = true,
+            "/rt" => in_ruby_text = false,
+            _ => {}
+        }
+        rest = &rest[open + close + 1..];
+    }
+    if !in_ruby_text {
+        text.push_str(rest);
+    }
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&lrm;", "\u{200e}")
+        .replace("&rlm;", "\u{200f}")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = "\u{feff}WEBVTT - Example\r
+Kind: captions\r
+\r
+STYLE\r
+::cue { color: white }\r
+\r
+NOTE this is a comment\r
+spanning two lines\r
+\r
+intro\r
+00:01.000 --> 00:02.500 align:start position:10%\r
+<v Steve>Hello</v> <c.yellow>world</c>\r
+\r
+2\r
+01:00:03.250 --> 01:00:04.000\r
+第一行\r
+<i>a &amp; b &lt;c&gt;</i>\r
+\r
+00:05.000 --> 00:06.000\r
+<ruby>漢<rt>かん</rt></ruby>字\r
+";
+
+        let clips = parse(contents).expect("failed to parse");
+        assert_eq!(clips.len(), 3);
+
+        assert_eq!(clips[0].index, 1);
+        assert_eq!(clips[0].start_time, Duration::from_millis(1000));
+        assert_eq!(clips[0].end_time, Duration::from_millis(2500));
+        assert_eq!(clips[0].text, "Hello world");
+
+        assert_eq!(clips[1].index, 2);
+        assert_eq!(clips[1].start_time, Duration::from_millis(3_603_250));
+        assert_eq!(clips[1].end_time, Duration::from_millis(3_604_000));
+        assert_eq!(clips[1].text, "第一行\na & b <c>");
+
+        assert_eq!(clips[2].text, "漢字");
+    }
+
+    #[test]
+    fn test_parse_missing_header() {
+        assert!(parse("00:01.000 --> 00:02.000\nHello\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_timestamp() {
+        let error = parse("WEBVTT\n\n00:01 --> 00:02.000\nHello\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 3: invalid timestamp \"00:01\"");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("00:00.001"), Ok(Duration::from_millis(1)));
+        assert_eq!(
+            parse_timestamp("123:00:00.000"),
+            Ok(Duration::from_secs(123 * 3600))
+        );
+        assert!(parse_timestamp("00:60.000").is_err());
+        assert!(parse_timestamp("00:00.1").is_err());
+    }
+}